@@ -0,0 +1,149 @@
+use crate::config::Config;
+use crate::outbox::Kind;
+use crate::store::Store;
+use failure::Error;
+use serde::Deserialize;
+use serde::Serialize;
+
+const DAY: u64 = 24 * 60 * 60;
+// held candidates older than this are dropped from the review file
+const KEEP: u64 = 7 * DAY;
+
+// a UTC window the bot stays quiet in: every day ("22:00-06:00", which wraps past midnight)
+// or once ("2026-10-20T18:00/2026-10-20T22:00")
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Period {
+  // minutes after midnight
+  Daily { start: u64, end: u64 },
+  // unix seconds
+  Once { start: u64, end: u64 },
+}
+
+impl Period {
+  pub fn parse(window: &str) -> Option<Period> {
+    if let Some((start, end)) = window.split_once('/') {
+      let (start, end) = (parse_datetime(start)?, parse_datetime(end)?);
+      return if start < end { Some(Period::Once{ start, end }) } else { None };
+    }
+    let (start, end) = window.split_once('-')?;
+    let (start, end) = (parse_time(start)?, parse_time(end)?);
+    if start == end {
+      return None;
+    }
+    Some(Period::Daily{ start, end })
+  }
+
+  pub fn contains(&self, now: u64) -> bool {
+    match *self {
+      Period::Daily{ start, end } => {
+        let minute = now % DAY / 60;
+        if start < end { minute >= start && minute < end } else { minute >= start || minute < end }
+      },
+      Period::Once{ start, end } => now >= start && now < end,
+    }
+  }
+}
+
+// "HH:MM" as minutes after midnight
+fn parse_time(time: &str) -> Option<u64> {
+  let (hours, minutes) = time.trim().split_once(':')?;
+  let (hours, minutes) = (hours.parse::<u64>().ok()?, minutes.parse::<u64>().ok()?);
+  if hours > 23 || minutes > 59 {
+    return None;
+  }
+  Some(hours * 60 + minutes)
+}
+
+// "YYYY-MM-DDTHH:MM", optionally ending in Z, as unix seconds
+fn parse_datetime(datetime: &str) -> Option<u64> {
+  let (date, time) = datetime.trim().trim_end_matches('Z').split_once('T')?;
+  let mut parts = date.splitn(3, '-').map(|part| part.parse::<i64>().ok());
+  let (year, month, day) = (parts.next()??, parts.next()??, parts.next()??);
+  if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+    return None;
+  }
+  let days = days_from_civil(year, month, day);
+  if days < 0 {
+    return None;
+  }
+  Some(days as u64 * DAY + parse_time(time)? * 60)
+}
+
+// days since 1970-01-01 of a proleptic Gregorian date
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+  let year = if month <= 2 { year - 1 } else { year };
+  let era = if year >= 0 { year } else { year - 399 } / 400;
+  let year_of_era = year - era * 400;
+  let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+  let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+  era * 146097 + day_of_era - 719468
+}
+
+// when, and where, the bot holds its replies
+pub struct Blackout {
+  windows: Vec<(String, Period)>,
+  posts: Vec<String>,
+  flairs: Vec<String>,
+}
+
+impl Blackout {
+  // windows that don't parse are left out; validation reports them
+  pub fn new(config: &Config) -> Blackout {
+    Blackout{
+      windows: config.quiet_hours.iter().filter_map(|window| Some((window.to_string(), Period::parse(window)?))).collect(),
+      posts: config.blackout_posts.clone(),
+      flairs: config.blackout_flairs.iter().map(|flair| flair.to_lowercase()).collect(),
+    }
+  }
+
+  // why a reply in post (with flair) would be held at now, if it would be
+  pub fn reason(&self, post: &str, flair: Option<&str>, now: u64) -> Option<String> {
+    if let Some((name, _)) = self.windows.iter().find(|(_, window)| window.contains(now)) {
+      return Some(format!("quiet hours {}", name));
+    }
+    if self.posts.iter().any(|id| id == post) {
+      return Some(format!("blackout post {}", post));
+    }
+    match flair {
+      Some(flair) if self.flairs.contains(&flair.to_lowercase()) => Some(format!("blackout flair {}", flair)),
+      _ => None,
+    }
+  }
+}
+
+// a reply that was held back, kept for moderators to review
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Held {
+  pub id: String,
+  pub kind: Kind,
+  pub thread: String,
+  pub sub: String,
+  pub rule: String,
+  pub reason: String,
+  pub at: u64,
+}
+
+pub struct Review {
+  pub held: Vec<Held>,
+}
+
+impl Review {
+  pub fn load<S: Store>(store: &S, name: &str, now: u64) -> Result<Review, Error> {
+    let mut held: Vec<Held> = match store.read(name) {
+      Some(contents) => serde_json::from_str(&contents)?,
+      None => Vec::new(),
+    };
+    held.retain(|held| held.at + KEEP > now);
+    Ok(Review{ held })
+  }
+
+  pub fn save<S: Store>(&self, store: &mut S, name: &str) -> Result<(), Error> {
+    store.write(name, &serde_json::to_string_pretty(&self.held)?)
+  }
+
+  // a candidate seen again replaces its earlier entry
+  pub fn hold(&mut self, held: Held) {
+    self.held.retain(|entry| entry.id != held.id);
+    self.held.push(held);
+  }
+}
@@ -0,0 +1,300 @@
+use crate::blackout::Blackout;
+use crate::blackout::Held;
+use crate::blackout::Review;
+use crate::config::Config;
+use crate::config::Sub;
+use crate::config::ThreadDedup;
+use crate::outbox;
+use crate::outbox::Entry;
+use crate::outbox::Kind;
+use crate::outbox::Outbox;
+use crate::post::Post;
+use crate::reddit;
+use crate::reddit::Circuit;
+use crate::reddit::RedditApp;
+use crate::store::get_db;
+use crate::store::write_db;
+use crate::store::Store;
+use crate::summary::Summary;
+use crate::throttle::History;
+use crate::throttle::Limits;
+use crate::throttle::Reply;
+use crate::logging;
+use crate::metrics;
+use failure::Error;
+use log::info;
+use log::warn;
+use std::collections::HashSet;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Instant;
+
+pub struct Bot<T: RedditApp, S: Store> {
+  pub app: T,
+  pub store: S,
+  pub config: Config,
+  // log replies but leave the store untouched
+  pub dry_run: bool,
+  // set to end the run early: the reply in flight finishes and the stores are still written
+  pub stop: Arc<AtomicBool>,
+}
+
+impl<T: RedditApp, S: Store> Bot<T, S> {
+  pub fn new(app: T, store: S, config: Config) -> Bot<T, S> {
+    Bot{ app, store, config, dry_run: false, stop: Arc::new(AtomicBool::new(false)) }
+  }
+
+  // asked to stop, or the circuit breaker has opened
+  fn stopping(&self) -> bool {
+    self.stop.load(Ordering::SeqCst) || self.app.circuit() == Circuit::Open
+  }
+
+  pub fn run(&mut self) -> Result<Summary, Error> {
+    let started = Instant::now();
+    let mut summary = Summary::default();
+    self.app.begin_run();
+    // the history is shared by every sub, so the limits apply to the bot as a whole
+    let mut history = History::load(&self.store, &self.config.history_filename, outbox::now())?;
+    let limits = Limits::new(&self.config);
+    let blackout = Blackout::new(&self.config);
+    let mut review = Review::load(&self.store, &self.config.held_filename, outbox::now())?;
+    for sub in self.config.subs() {
+      if self.stopping() {
+        break;
+      }
+      self.run_sub(&sub, &limits, &mut history, &blackout, &mut review, &mut summary)?;
+      if !self.dry_run {
+        let start = Instant::now();
+        history.save(&mut self.store, &self.config.history_filename)?;
+        review.save(&mut self.store, &self.config.held_filename)?;
+        summary.time("save", start);
+        metrics::set("rrbot_store_entries", &[("store", "history")], history.replies.len() as f64);
+        metrics::set("rrbot_store_entries", &[("store", "held")], review.held.len() as f64);
+      }
+    }
+    summary.elapsed_secs = started.elapsed().as_secs_f64();
+    metrics::observe("rrbot_scan_duration_seconds", &[], summary.elapsed_secs);
+    metrics::set("rrbot_last_scan_timestamp_seconds", &[], outbox::now() as f64);
+    info!("Run summary: {}", summary);
+    if let (Some(filename), false) = (&self.config.summary_filename, self.dry_run) {
+      self.store.write(filename, &serde_json::to_string_pretty(&summary)?)?;
+    }
+    Ok(summary)
+  }
+
+  fn run_sub(&mut self, sub: &Sub, limits: &Limits, history: &mut History, blackout: &Blackout, review: &mut Review, summary: &mut Summary) -> Result<(), Error> {
+    let app = &self.app;
+    let config = &self.config;
+    let (mut post_replies, mut comment_replies) = (0, 0);
+    let _sub = logging::context("sub", &sub.name);
+
+    // get existing replied to posts, comments and inbox replies
+    let mut comments_db = get_db(&self.store, &sub.comments_db_filename);
+    let mut posts_db = get_db(&self.store, &sub.posts_db_filename);
+    //let mut inbox_db = get_db(&self.store, &config.inbox_db_filename);
+    let mut outbox = Outbox::load(&self.store, &sub.outbox_filename, config.outbox_max_attempts, config.outbox_backoff_secs)?;
+
+    // retry replies that failed on previous runs
+    let start = Instant::now();
+    let drained = outbox.drain(app, history, limits, blackout, &sub.name, outbox::now());
+    summary.retries_sent += drained.sent.len() as u32;
+    summary.replies_sent += drained.sent.len() as u32;
+    (0..outbox.failed - outbox.gave_up).for_each(|_| summary.error("retry"));
+    (0..outbox.gave_up).for_each(|_| summary.error("gave_up"));
+    drained.rejected.iter().for_each(|_| summary.error("rejected"));
+    // reddit won't ever take these, so they're marked as handled rather than tried again
+    for entry in drained.sent.into_iter().chain(drained.rejected) {
+      match entry.kind {
+        Kind::Post => posts_db.insert(entry.id),
+        Kind::Comment => comments_db.insert(entry.id),
+      };
+    }
+    // never answered, so left unmarked: a later run that still sees it starts over
+    for entry in drained.gave_up {
+      warn!("Leaving {} unanswered in r/{}: {}", entry.id, sub.name, entry.last_error);
+    }
+    summary.time("outbox", start);
+
+    // get new posts from each listing, once each, and check for post and comment matches
+    let start = Instant::now();
+    let mut seen = HashSet::new();
+    let mut posts = Vec::new();
+    for sort in &sub.sorts {
+      match app.get_posts(&sub.name, *sort, sub.hot_take as usize) {
+        Ok(listing) => {
+          summary.listings_read += 1;
+          posts.extend(listing.into_iter().filter(|json| seen.insert(json["data"]["id"].to_string())));
+        },
+        Err(e) => {
+          warn!("Error reading r/{} ({}): {}", sub.name, sort, e);
+          summary.error("listing");
+        },
+      }
+    }
+    summary.time("listings", start);
+    let start = Instant::now();
+    let count = posts.len();
+    summary.subs += 1;
+    for json in &posts {
+      if self.stopping() {
+        info!("Stopping: leaving the rest of r/{} for the next run", sub.name);
+        break;
+      }
+      let post = Post::new(&json["data"].to_string(), app)?;
+      let _post = logging::context("post", &post.id);
+      summary.posts_scanned += 1;
+
+      // the post and its comments that match a rule and haven't been answered yet
+      let mut matches = Vec::new();
+      if let Some(rule) = post.matching_rule(&sub.rules) {
+        matches.push((post.id.to_string(), Kind::Post, post.author.to_string(), rule));
+      }
+      // the post can still be answered if its comments can't be read
+      match post.scan_comments(&sub.rules) {
+        Ok((comments, matching_comments)) => {
+          summary.comments_scanned += comments as u32;
+          matches.extend(matching_comments.into_iter().map(|(comment, rule)| (comment.id, Kind::Comment, comment.author, rule)));
+        },
+        Err(e) => {
+          warn!("Error reading the comments on {} in r/{}: {}", post.id, sub.name, e);
+          summary.error("comments");
+        },
+      }
+      let mut candidates = Vec::new();
+      for (id, kind, author, rule) in matches {
+        summary.matched(&rule.name);
+        let db = match kind {
+          Kind::Post => &posts_db,
+          Kind::Comment => &comments_db,
+        };
+        if db.contains(&id) {
+          summary.skip("already_replied");
+        } else if outbox.contains(&id) {
+          summary.skip("queued");
+        } else {
+          // not counted again once it's answered or queued; one held back or over a cap is
+          // counted each scan it's seen in
+          metrics::inc("rrbot_matches_total", &[("rule", &rule.name)]);
+          candidates.push((id, kind, author, rule));
+        }
+      }
+
+      for (id, kind, author, rule) in candidates {
+        if self.stopping() {
+          break;
+        }
+        let (what, db) = match kind {
+          Kind::Post => ("post", &mut posts_db),
+          Kind::Comment => ("comment", &mut comments_db),
+        };
+        let _comment = (kind == Kind::Comment).then(|| logging::context("comment", &id));
+        let _rule = logging::context("rule", &rule.name);
+
+        // held for review rather than answered; left unmarked so it can be answered once the
+        // window closes or the blackout is lifted
+        if let Some(reason) = blackout.reason(&post.id, post.link_flair_text.as_deref(), outbox::now()) {
+          info!("Holding {} {} in r/{}: {}", what, id, sub.name, reason);
+          summary.skip("blackout");
+          review.hold(Held{
+            id,
+            kind,
+            thread: post.id.to_string(),
+            sub: sub.name.to_string(),
+            rule: rule.name.to_string(),
+            reason,
+            at: outbox::now(),
+          });
+          continue;
+        }
+
+        let mut body = rule.reply.to_string();
+        if config.thread_dedup != ThreadDedup::Off {
+          if let Some(first) = history.first_reply(&post.id, &rule.name) {
+            match (config.thread_dedup, &first.comment) {
+              (ThreadDedup::Link, Some(comment)) => {
+                let link = format!("https://www.reddit.com/r/{}/comments/{}/_/{}/", sub.name, post.id, comment);
+                body = config.see_above_reply.replace("{link}", &link);
+              },
+              _ => {
+                // answered already; marked as handled so later runs don't look at it again
+                info!("Skipping {} {} in r/{}: already answered in this thread (rule: {})", what, id, sub.name, rule.name);
+                summary.skip("thread_dedup");
+                db.insert(id);
+                continue;
+              },
+            }
+          }
+        }
+
+        // a throttled candidate isn't marked as replied to, so a later run can still answer it
+        if let Some((limit, value)) = history.limited(limits, &post.id, &author, outbox::now()) {
+          info!("Skipping {} {} in r/{}: {} ({}) reached", what, id, sub.name, limit, value);
+          summary.skip(limit);
+          continue;
+        }
+        info!("Replying to {} {} in r/{} (rule: {})", what, id, sub.name, rule.name);
+        match app.reply(&id, &rule.name, &body) {
+          Ok(comment) => {
+            history.record(Reply{
+              id: id.to_string(),
+              thread: post.id.to_string(),
+              author,
+              sub: sub.name.to_string(),
+              rule: rule.name.to_string(),
+              at: outbox::now(),
+              comment,
+            });
+            db.insert(id);
+            summary.replies_sent += 1;
+            metrics::inc("rrbot_replies_total", &[("rule", &rule.name)]);
+            match kind {
+              Kind::Post => post_replies += 1,
+              Kind::Comment => comment_replies += 1,
+            };
+          },
+          // trying again wouldn't help, so it's marked as handled rather than queued
+          Err(e) if reddit::permanent(&e) => {
+            warn!("Reddit won't take the reply to {} {}, marking it handled: {}", what, id, e);
+            summary.error("rejected");
+            metrics::inc("rrbot_reply_errors_total", &[("rule", &rule.name)]);
+            db.insert(id);
+          },
+          Err(e) => {
+            warn!("Error replying to {} {}: {}", what, id, e);
+            summary.error("reply");
+            metrics::inc("rrbot_reply_errors_total", &[("rule", &rule.name)]);
+            let entry = Entry{
+              thread: post.id.to_string(),
+              flair: post.link_flair_text.clone(),
+              author,
+              ..Entry::new(&id, kind, &rule.name, &body)
+            };
+            outbox.push(entry, &e, outbox::now());
+          }
+        };
+      }
+    }
+    info!("r/{}: scanned {} posts, replied to {} posts and {} comments, {} queued for retry",
+      sub.name, count, post_replies, comment_replies, outbox.entries.len());
+    summary.queued += outbox.entries.len() as u32;
+    summary.time("threads", start);
+
+    if self.dry_run {
+      return Ok(());
+    }
+
+    let start = Instant::now();
+    write_db(&mut self.store, &sub.posts_db_filename, &posts_db)?;
+    write_db(&mut self.store, &sub.comments_db_filename, &comments_db)?;
+    outbox.save(&mut self.store, &sub.outbox_filename)?;
+    summary.time("save", start);
+    let stores = [("posts_db", posts_db.len()), ("comments_db", comments_db.len()), ("outbox", outbox.entries.len())];
+    for (store, entries) in stores {
+      metrics::set("rrbot_store_entries", &[("store", store), ("sub", &sub.name)], entries as f64);
+    }
+    //write_db(&mut self.store, &config.inbox_db_filename, &inbox_db)?;
+
+    Ok(())
+  }
+}
@@ -0,0 +1,156 @@
+use crate::reddit;
+use crate::reddit::AuthFailed;
+use crate::reddit::Circuit;
+use crate::reddit::RedditApp;
+use crate::reddit::Sort;
+use crate::store::Store;
+use failure::Error;
+use failure::format_err;
+use log::info;
+use log::warn;
+use orca::data::Comment;
+use orca::data::Listing;
+use serde::Deserialize;
+use serde::Serialize;
+use std::cell::Cell;
+use std::cell::RefCell;
+use std::fmt;
+
+// the run was cut short because the breaker opened
+#[derive(Debug)]
+pub struct CircuitOpen {
+  pub failures: u32,
+  pub last_error: String,
+  pub skipped: u32,
+}
+
+impl fmt::Display for CircuitOpen {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "Circuit open after {} consecutive failed reddit calls (last: {}); ended the run, skipping {} calls. The next run tries again.",
+      self.failures, self.last_error, self.skipped
+    )
+  }
+}
+
+impl std::error::Error for CircuitOpen {}
+
+// stops calling reddit after threshold calls in a row fail, so a degraded api isn't hit with
+// a call (and an error) per post and comment. Only calls that can't reach reddit or get a 5xx
+// count; one it answers but turns down (a 4xx, a rejected reply) shows it's up. Calls fail
+// straight away while open; the next run lets one call through (half-open) and closes again if
+// it succeeds. The state is saved between runs, so this holds for cron runs as well as daemon
+// scans. Credentials turned away open it at once, since they won't start working again during
+// the run
+pub struct Breaker<T: RedditApp> {
+  pub inner: T,
+  threshold: u32,
+  state: Cell<Circuit>,
+  failures: Cell<u32>,
+  skipped: Cell<u32>,
+  last_error: RefCell<String>,
+  // why reddit turned the credentials away, if that's what opened it
+  auth_error: RefCell<Option<String>>,
+}
+
+// the breaker's state as one run leaves it for the next
+#[derive(Serialize, Deserialize, Default)]
+struct Saved {
+  circuit: Circuit,
+  failures: u32,
+  last_error: String,
+}
+
+impl<T: RedditApp> Breaker<T> {
+  pub fn new(inner: T, threshold: u32) -> Breaker<T> {
+    Breaker{
+      inner,
+      threshold,
+      state: Cell::new(Circuit::Closed),
+      failures: Cell::new(0),
+      skipped: Cell::new(0),
+      last_error: RefCell::new(String::new()),
+      auth_error: RefCell::new(None),
+    }
+  }
+
+  // picks up where the last run left off; nothing saved yet is a closed circuit
+  pub fn load<S: Store>(&self, store: &S, name: &str) -> Result<(), Error> {
+    if let Some(contents) = store.read(name) {
+      let saved: Saved = serde_json::from_str(&contents)?;
+      self.state.set(saved.circuit);
+      self.failures.set(saved.failures);
+      *self.last_error.borrow_mut() = saved.last_error;
+    }
+    Ok(())
+  }
+
+  pub fn save<S: Store>(&self, store: &mut S, name: &str) -> Result<(), Error> {
+    let saved = Saved{ circuit: self.state.get(), failures: self.failures.get(), last_error: self.last_error.borrow().to_string() };
+    store.write(name, &serde_json::to_string_pretty(&saved)?)
+  }
+
+  // what to report once the breaker has opened
+  pub fn summary(&self) -> CircuitOpen {
+    CircuitOpen{ failures: self.failures.get(), last_error: self.last_error.borrow().to_string(), skipped: self.skipped.get() }
+  }
+
+  // the error to end the run with if it opened because the credentials were turned away
+  pub fn auth_error(&self) -> Option<AuthFailed> {
+    self.auth_error.borrow().as_ref().map(|message| AuthFailed(message.to_string()))
+  }
+
+  fn close(&self) {
+    self.failures.set(0);
+    self.state.set(Circuit::Closed);
+    *self.auth_error.borrow_mut() = None;
+  }
+
+  fn call<R, F>(&self, call: F) -> Result<R, Error>
+  where F: FnOnce(&T) -> Result<R, Error>
+  {
+    if self.state.get() == Circuit::Open {
+      self.skipped.set(self.skipped.get() + 1);
+      return Err(format_err!("Circuit open; call skipped"));
+    }
+    let result = call(&self.inner);
+    match &result {
+      // reddit answered, so it's up, even if it turned the call down
+      Ok(_) => self.close(),
+      Err(e) if reddit::refused(e) => self.close(),
+      Err(e) => {
+        self.failures.set(self.failures.get() + 1);
+        *self.last_error.borrow_mut() = e.to_string();
+        *self.auth_error.borrow_mut() = e.downcast_ref::<AuthFailed>().map(|AuthFailed(message)| message.to_string());
+        if self.state.get() == Circuit::HalfOpen || self.failures.get() >= self.threshold || self.auth_error.borrow().is_some() {
+          warn!("Circuit open after {} consecutive failed reddit calls", self.failures.get());
+          self.state.set(Circuit::Open);
+        }
+      },
+    }
+    result
+  }
+}
+
+impl<T: RedditApp> RedditApp for Breaker<T> {
+  fn get_comment_tree(&self, post_id: &str) -> Result<Listing<Comment>, Error> {
+    self.call(|inner| inner.get_comment_tree(post_id))
+  }
+  fn get_posts(&self, sub: &str, sort: Sort, depth: usize) -> Result<Vec<serde_json::Value>, Error> {
+    self.call(|inner| inner.get_posts(sub, sort, depth))
+  }
+  fn reply(&self, id: &str, rule: &str, body: &str) -> Result<Option<String>, Error> {
+    self.call(|inner| inner.reply(id, rule, body))
+  }
+  fn circuit(&self) -> Circuit {
+    self.state.get()
+  }
+  fn begin_run(&self) {
+    if self.state.get() == Circuit::Open {
+      info!("Circuit half-open: trying reddit again");
+      self.state.set(Circuit::HalfOpen);
+      self.skipped.set(0);
+    }
+  }
+}
@@ -1,23 +1,515 @@
+use failure::bail;
+use failure::format_err;
+use failure::Error;
 use serde::Deserialize;
+use serde::Serialize;
 use serde_json::Result;
+use serde_json::Value;
+use crate::validate::validate;
+use crate::validate::Invalid;
+use crate::post::Rule;
+use crate::post::RULES;
+use crate::reddit::Sort;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct Config {
   pub client_id: String,
   pub client_secret: String,
   pub username: String,
   pub password: String,
-  pub hot_take: u8,
+  pub hot_take: u32,
   pub inbox_db_filename: String,
   pub posts_db_filename: String,
   pub comments_db_filename: String,
+  // either a single sub, or a list of them under subs
+  #[serde(default)]
   pub sub: String,
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub subs: Vec<SubConfig>,
+  // listings to scan, each up to hot_take posts; a post in several is only checked once
+  #[serde(default = "default_sorts")]
+  pub sorts: Vec<String>,
+  // listings are read up to 100 posts a page, waiting page_delay_ms between pages
+  #[serde(default = "default_max_pages")]
+  pub max_pages: u32,
+  #[serde(default = "default_page_delay_ms")]
+  pub page_delay_ms: u64,
+  // replies made in the last week, for the max_replies_* limits
+  #[serde(default = "default_history_filename")]
+  pub history_filename: String,
+  // caps on replies; unset is no cap. per_hour is over the last hour, per_thread over one post
+  // and its comments, per_author to one author over the last day
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub max_replies_per_run: Option<u32>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub max_replies_per_hour: Option<u32>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub max_replies_per_thread: Option<u32>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub max_replies_per_author: Option<u32>,
+  // what to do with a match in a thread the bot has already answered for the same rule:
+  // off replies as usual, skip doesn't reply, link replies with see_above_reply
+  #[serde(default)]
+  pub thread_dedup: ThreadDedup,
+  // {link} is replaced with a link to the bot's first reply
+  #[serde(default = "default_see_above_reply")]
+  pub see_above_reply: String,
+  // no replies while any of these UTC windows is open ("22:00-06:00" daily, or
+  // "2026-10-20T18:00/2026-10-20T22:00" once), nor in these posts or posts with these flairs;
+  // the replies held back are recorded in held_filename for review
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub quiet_hours: Vec<String>,
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub blackout_posts: Vec<String>,
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub blackout_flairs: Vec<String>,
+  #[serde(default = "default_held_filename")]
+  pub held_filename: String,
+  // a run ends after this many reddit calls in a row fail; the breaker's state is kept in
+  // breaker_filename for the next run
+  #[serde(default = "default_breaker_threshold")]
+  pub breaker_threshold: u32,
+  #[serde(default = "default_breaker_filename")]
+  pub breaker_filename: String,
+  // a level, optionally with levels for particular modules ("warn,reddit=debug"), and
+  // text or json lines
+  #[serde(default = "default_log_level")]
+  pub log_level: String,
+  #[serde(default)]
+  pub log_format: LogFormat,
+  // where to write each run's summary as JSON, if anywhere
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub summary_filename: Option<String>,
+  // rrbot daemon serves prometheus metrics on 127.0.0.1:metrics_port/metrics; any run
+  // writes them to metrics_textfile (a .prom file) for the node exporter's textfile collector
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub metrics_port: Option<u16>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub metrics_textfile: Option<String>,
+  // /healthz fails once the last successful scan is older than this; three intervals (with
+  // jitter) if not set
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub health_max_age_secs: Option<u64>,
+  // held by the running bot so overlapping runs don't share the stores
+  #[serde(default = "default_lock_filename")]
+  pub lock_filename: String,
+  // rrbot daemon scans every interval_secs, plus up to jitter_secs
+  #[serde(default = "default_interval_secs")]
+  pub interval_secs: u64,
+  #[serde(default = "default_jitter_secs")]
+  pub jitter_secs: u64,
+  // the user agent is <platform>:<app_id>:<app_version> (by u/<contact>), as reddit's API
+  // rules ask; contact defaults to the bot's own username
+  #[serde(default = "default_platform")]
+  pub platform: String,
+  #[serde(default = "default_app_id")]
+  pub app_id: String,
+  #[serde(default = "default_app_version")]
+  pub app_version: String,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub contact: Option<String>,
+  #[serde(default = "default_api_url")]
+  pub api_url: String,
+  #[serde(default = "default_auth_url")]
+  pub auth_url: String,
+  // the longest a request to reddit may take, connecting included, before it fails
+  #[serde(default = "default_request_timeout_secs")]
+  pub request_timeout_secs: u64,
+  #[serde(default = "default_outbox_filename")]
+  pub outbox_filename: String,
+  #[serde(default = "default_outbox_max_attempts")]
+  pub outbox_max_attempts: u32,
+  #[serde(default = "default_outbox_backoff_secs")]
+  pub outbox_backoff_secs: u64,
+  // a JSON file holding client_secret and password, readable only by the bot's user
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub secrets_filename: Option<String>,
+}
+
+// the config file couldn't be read, parsed or validated
+#[derive(Debug)]
+pub struct ConfigError(pub String);
+
+impl fmt::Display for ConfigError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.0)
+  }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ThreadDedup {
+  #[default]
+  Off,
+  Skip,
+  Link,
+}
+
+pub const THREAD_DEDUP: &[&str] = &["off", "skip", "link"];
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+  #[default]
+  Text,
+  Json,
+}
+
+pub const LOG_FORMATS: &[&str] = &["text", "json"];
+
+// per-subreddit settings; anything left out falls back to the top level config
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct SubConfig {
+  pub name: String,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub hot_take: Option<u32>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub sorts: Option<Vec<String>>,
+  // names of the rules to answer; all of them if left out
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub rules: Option<Vec<String>>,
+  // rule name -> reply to make instead of the rule's own
+  #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+  pub replies: HashMap<String, String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub posts_db_filename: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub comments_db_filename: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub outbox_filename: Option<String>,
+}
+
+// a sub with its settings resolved against the top level config
+#[derive(Clone)]
+pub struct Sub {
+  pub name: String,
+  pub hot_take: u32,
+  pub sorts: Vec<Sort>,
+  pub rules: Vec<Rule>,
+  pub posts_db_filename: String,
+  pub comments_db_filename: String,
+  pub outbox_filename: String,
+}
+
+// "posts.db" becomes "posts.<sub>.db", so each sub keeps its own record of what it's seen
+pub fn namespaced(filename: &str, sub: &str) -> String {
+  let path = Path::new(filename);
+  let stem = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or(filename);
+  let name = match path.extension().and_then(|extension| extension.to_str()) {
+    Some(extension) => format!("{}.{}.{}", stem, sub, extension),
+    None => format!("{}.{}", stem, sub),
+  };
+  path.with_file_name(name).to_str().unwrap_or(filename).to_string()
+}
+
+fn default_sorts() -> Vec<String> { vec!["hot".to_string()] }
+fn default_max_pages() -> u32 { 10 }
+fn default_page_delay_ms() -> u64 { 1000 }
+fn default_history_filename() -> String { "history.json".to_string() }
+fn default_see_above_reply() -> String { "Answered above: {link}\n*****\n^(I am a bot, flex-beep-boop)".to_string() }
+fn default_held_filename() -> String { "held.json".to_string() }
+fn default_log_level() -> String { "info".to_string() }
+fn default_breaker_threshold() -> u32 { 5 }
+fn default_breaker_filename() -> String { "breaker.json".to_string() }
+fn default_lock_filename() -> String { "rrbot.lock".to_string() }
+fn default_interval_secs() -> u64 { 300 }
+fn default_jitter_secs() -> u64 { 30 }
+fn default_platform() -> String { std::env::consts::OS.to_string() }
+fn default_app_id() -> String { "rrbot".to_string() }
+fn default_app_version() -> String { env!("CARGO_PKG_VERSION").to_string() }
+fn default_api_url() -> String { "https://oauth.reddit.com".to_string() }
+fn default_auth_url() -> String { "https://www.reddit.com".to_string() }
+fn default_request_timeout_secs() -> u64 { 30 }
+fn default_outbox_filename() -> String { "outbox.json".to_string() }
+fn default_outbox_max_attempts() -> u32 { 5 }
+fn default_outbox_backoff_secs() -> u64 { 300 }
+
+pub const DEFAULT_PATH: &str = "config.json";
+
+// the config file format, chosen by extension; anything other than .toml, .yaml or .yml is JSON
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Format {
+  Json,
+  Toml,
+  Yaml,
+}
+
+impl Format {
+  pub fn from_path(path: &str) -> Format {
+    match Path::new(path).extension().and_then(|extension| extension.to_str()) {
+      Some("toml") => Format::Toml,
+      Some("yaml") | Some("yml") => Format::Yaml,
+      _ => Format::Json,
+    }
+  }
+
+  // every format is read into the same JSON value so layering and validation don't care
+  pub fn parse(self, contents: &str) -> std::result::Result<Value, Error> {
+    let config = match self {
+      Format::Json => serde_json::from_str(contents).map_err(Error::from),
+      Format::Toml => toml::from_str(contents).map_err(Error::from),
+      Format::Yaml => serde_yaml::from_str(contents).map_err(Error::from),
+    };
+    config.map_err(|e| format_err!("Error parsing config file: {}", e))
+  }
+}
+pub const ENV_PREFIX: &str = "RRBOT_";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Kind {
+  Text,
+  Number,
+  // subs: set from the environment or command line as a comma separated list of names
+  Subs,
+  // sorts: likewise a comma separated list, e.g. hot,new
+  Sorts,
+  // a list of strings, comma separated from the environment or command line
+  List,
+}
+
+// fields that can be set from the environment (RRBOT_SUB) or command line (--sub)
+pub const FIELDS: &[(&str, Kind)] = &[
+  ("client_id", Kind::Text),
+  ("client_secret", Kind::Text),
+  ("username", Kind::Text),
+  ("password", Kind::Text),
+  ("hot_take", Kind::Number),
+  ("inbox_db_filename", Kind::Text),
+  ("posts_db_filename", Kind::Text),
+  ("comments_db_filename", Kind::Text),
+  ("sub", Kind::Text),
+  ("subs", Kind::Subs),
+  ("sorts", Kind::Sorts),
+  ("max_pages", Kind::Number),
+  ("page_delay_ms", Kind::Number),
+  ("history_filename", Kind::Text),
+  ("max_replies_per_run", Kind::Number),
+  ("max_replies_per_hour", Kind::Number),
+  ("max_replies_per_thread", Kind::Number),
+  ("max_replies_per_author", Kind::Number),
+  ("thread_dedup", Kind::Text),
+  ("see_above_reply", Kind::Text),
+  ("quiet_hours", Kind::List),
+  ("blackout_posts", Kind::List),
+  ("blackout_flairs", Kind::List),
+  ("held_filename", Kind::Text),
+  ("breaker_threshold", Kind::Number),
+  ("breaker_filename", Kind::Text),
+  ("log_level", Kind::Text),
+  ("log_format", Kind::Text),
+  ("summary_filename", Kind::Text),
+  ("metrics_port", Kind::Number),
+  ("metrics_textfile", Kind::Text),
+  ("health_max_age_secs", Kind::Number),
+  ("lock_filename", Kind::Text),
+  ("interval_secs", Kind::Number),
+  ("jitter_secs", Kind::Number),
+  ("platform", Kind::Text),
+  ("app_id", Kind::Text),
+  ("app_version", Kind::Text),
+  ("contact", Kind::Text),
+  ("api_url", Kind::Text),
+  ("auth_url", Kind::Text),
+  ("request_timeout_secs", Kind::Number),
+  ("outbox_filename", Kind::Text),
+  ("outbox_max_attempts", Kind::Number),
+  ("outbox_backoff_secs", Kind::Number),
+  ("secrets_filename", Kind::Text),
+];
+
+pub const REQUIRED: &[&str] = &[
+  "client_id",
+  "client_secret",
+  "username",
+  "password",
+  "hot_take",
+  "inbox_db_filename",
+  "posts_db_filename",
+  "comments_db_filename",
+];
+
+pub const SECRETS: &[&str] = &["client_secret", "password"];
+
+// systemd's LoadCredential= puts one file per credential in this directory
+const CREDENTIALS_DIRECTORY: &str = "CREDENTIALS_DIRECTORY";
+
+fn field_value(name: &str, raw: &str) -> Option<Value> {
+  let (_, kind) = FIELDS.iter().find(|(field, _)| *field == name)?;
+  match (kind, raw.parse::<u64>()) {
+    (Kind::Number, Ok(n)) => Some(Value::from(n)),
+    (Kind::Subs, _) => Some(raw.split(',').map(|sub| serde_json::json!({ "name": sub.trim() })).collect()),
+    (Kind::Sorts, _) | (Kind::List, _) => Some(raw.split(',').map(|item| Value::from(item.trim())).filter(|item| item != "").collect()),
+    _ => Some(Value::from(raw)),
+  }
 }
 
 impl Config {
   pub fn new(config: &str) -> Result<Config> {
-    let config: Config = serde_json::from_str(&config).expect("Error parsing config file");
+    serde_json::from_str(config)
+  }
+
+  // the config file, then RRBOT_* environment variables, then command line overrides
+  pub fn load<I>(path: &str, env: I, overrides: &[(String, String)]) -> std::result::Result<Config, Error>
+  where I: IntoIterator<Item=(String, String)>
+  {
+    let contents = fs::read_to_string(path).map_err(|e| ConfigError(format!("Error reading config file {}: {}", path, e)))?;
+    Config::layered(&contents, Format::from_path(path), env, overrides).map_err(|e| ConfigError(format!("{}: {}", path, e)).into())
+  }
+
+  pub fn layered<I>(contents: &str, format: Format, env: I, overrides: &[(String, String)]) -> std::result::Result<Config, Error>
+  where I: IntoIterator<Item=(String, String)>
+  {
+    let mut config = format.parse(contents)?;
+    let env = env.into_iter().collect::<Vec<_>>();
+    let fields = match config.as_object_mut() {
+      Some(fields) => fields,
+      None => bail!("Error parsing config file: expected a JSON object"),
+    };
+
+    let mut layers = Vec::new();
+    for (key, raw) in &env {
+      if let Some(name) = key.strip_prefix(ENV_PREFIX) {
+        let name = name.to_lowercase();
+        if let Some(value) = field_value(&name, raw) {
+          layers.push((name, value));
+        }
+      }
+    }
+    for (name, raw) in overrides {
+      match field_value(name, raw) {
+        Some(value) => layers.push((name.to_string(), value)),
+        None => bail!("Unknown config field: {}", name),
+      };
+    }
+
+    // secrets sit between the config file and the environment
+    let secrets_filename = layers.iter().rev()
+      .find(|(name, _)| name == "secrets_filename")
+      .map(|(_, value)| value)
+      .or_else(|| fields.get("secrets_filename"))
+      .and_then(Value::as_str)
+      .map(|path| path.to_string());
+    if let Some(path) = secrets_filename {
+      fields.extend(read_secrets_file(&path)?);
+    }
+    if let Some((_, dir)) = env.iter().find(|(key, _)| key == CREDENTIALS_DIRECTORY) {
+      fields.extend(read_credentials(Path::new(dir))?);
+    }
+
+    fields.extend(layers);
+
+    let problems = validate(&config);
+    if !problems.is_empty() {
+      return Err(Invalid{ problems }.into());
+    }
+    Ok(Config::new(&config.to_string())?)
+  }
+
+  // the subs to scan; a lone sub, set by sub or as the only entry in subs, keeps the top level
+  // db filenames as they are, so moving it from one to the other keeps its history
+  pub fn subs(&self) -> Vec<Sub> {
+    if self.subs.is_empty() {
+      let sub = SubConfig{ name: self.sub.to_string(), ..SubConfig::default() };
+      return vec![self.resolve(&sub, false)];
+    }
+    self.subs.iter().map(|sub| self.resolve(sub, self.subs.len() > 1)).collect()
+  }
+
+  fn resolve(&self, sub: &SubConfig, namespace: bool) -> Sub {
+    let filename = |own: &Option<String>, shared: &str| match own {
+      Some(filename) => filename.to_string(),
+      None if namespace => namespaced(shared, &sub.name),
+      None => shared.to_string(),
+    };
+    let rules = RULES.iter()
+      .filter(|rule| match &sub.rules {
+        Some(names) => names.contains(&rule.name),
+        None => true,
+      })
+      .map(|rule| match sub.replies.get(&rule.name) {
+        Some(reply) => Rule{ reply: reply.to_string(), ..rule.clone() },
+        None => rule.clone(),
+      })
+      .collect();
+    Sub{
+      name: sub.name.to_string(),
+      hot_take: sub.hot_take.unwrap_or(self.hot_take),
+      sorts: sub.sorts.as_ref().unwrap_or(&self.sorts).iter().filter_map(|sort| Sort::parse(sort)).collect(),
+      rules,
+      posts_db_filename: filename(&sub.posts_db_filename, &self.posts_db_filename),
+      comments_db_filename: filename(&sub.comments_db_filename, &self.comments_db_filename),
+      outbox_filename: filename(&sub.outbox_filename, &self.outbox_filename),
+    }
+  }
+
+  pub fn user_agent(&self) -> String {
+    let contact = self.contact.as_ref().unwrap_or(&self.username);
+    format!("{}:{}:{} (by u/{})", self.platform, self.app_id, self.app_version, contact)
+  }
 
-    Ok(config)
+  pub fn health_max_age(&self) -> u64 {
+    self.health_max_age_secs.unwrap_or(3 * (self.interval_secs + self.jitter_secs))
   }
-}
\ No newline at end of file
+
+  // the effective config as JSON, with secrets blanked out
+  pub fn redacted(&self) -> String {
+    let mut config = serde_json::to_value(self).unwrap_or_default();
+    SECRETS.iter().for_each(|field| config[field] = Value::from("<redacted>"));
+    serde_json::to_string_pretty(&config).unwrap_or_default()
+  }
+}
+
+// so that logging a Config never leaks credentials
+impl fmt::Debug for Config {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.redacted())
+  }
+}
+
+#[cfg(unix)]
+fn check_permissions(path: &str) -> std::result::Result<(), Error> {
+  use std::os::unix::fs::PermissionsExt;
+  let mode = fs::metadata(path)?.permissions().mode();
+  if mode & 0o004 != 0 {
+    bail!("it is world-readable (mode {:o}); chmod 600 it", mode & 0o777);
+  }
+  Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_permissions(_path: &str) -> std::result::Result<(), Error> {
+  Ok(())
+}
+
+fn read_secrets_file(path: &str) -> std::result::Result<Vec<(String, Value)>, Error> {
+  check_permissions(path).map_err(|e| format_err!("Error reading secrets file {}: {}", path, e))?;
+  let contents = fs::read_to_string(path).map_err(|e| format_err!("Error reading secrets file {}: {}", path, e))?;
+  let secrets = match Format::from_path(path).parse(&contents)? {
+    Value::Object(secrets) => secrets,
+    _ => bail!("Error reading secrets file {}: expected an object", path),
+  };
+  for name in secrets.keys() {
+    if !SECRETS.contains(&name.as_str()) {
+      bail!("Unknown field in secrets file {}: {}", path, name);
+    }
+  }
+  Ok(secrets.into_iter().collect())
+}
+
+fn read_credentials(dir: &Path) -> std::result::Result<Vec<(String, Value)>, Error> {
+  let mut credentials = Vec::new();
+  for name in SECRETS {
+    let path = dir.join(name);
+    if path.exists() {
+      let secret = fs::read_to_string(&path)?;
+      credentials.push((name.to_string(), Value::from(secret.trim_end_matches('\n'))));
+    }
+  }
+  Ok(credentials)
+}
@@ -0,0 +1,74 @@
+use failure::Error;
+use signal_hook::consts::SIGHUP;
+use signal_hook::consts::SIGINT;
+use signal_hook::consts::SIGTERM;
+use signal_hook::flag;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+// how often a sleeping daemon looks at the signal flags
+const POLL: Duration = Duration::from_millis(100);
+
+// SIGTERM and SIGINT set stop, SIGHUP sets reload; the daemon loop and the bot check them
+// between steps, so a signal never cuts a reply or a store write short
+pub struct Signals {
+  pub stop: Arc<AtomicBool>,
+  pub reload: Arc<AtomicBool>,
+}
+
+impl Signals {
+  pub fn new() -> Signals {
+    Signals{ stop: Arc::new(AtomicBool::new(false)), reload: Arc::new(AtomicBool::new(false)) }
+  }
+
+  pub fn register() -> Result<Signals, Error> {
+    let signals = Signals::new();
+    flag::register(SIGTERM, Arc::clone(&signals.stop))?;
+    flag::register(SIGINT, Arc::clone(&signals.stop))?;
+    flag::register(SIGHUP, Arc::clone(&signals.reload))?;
+    Ok(signals)
+  }
+
+  pub fn stopping(&self) -> bool {
+    self.stop.load(Ordering::SeqCst)
+  }
+
+  // true once per SIGHUP
+  pub fn take_reload(&self) -> bool {
+    self.reload.swap(false, Ordering::SeqCst)
+  }
+
+  // sleeps for duration, waking early if asked to stop or reload
+  pub fn sleep(&self, duration: Duration) {
+    let until = Instant::now() + duration;
+    while !self.stopping() && !self.reload.load(Ordering::SeqCst) {
+      let now = Instant::now();
+      if now >= until {
+        break;
+      }
+      thread::sleep(std::cmp::min(POLL, until - now));
+    }
+  }
+
+  // sleeps until the next scan is due, calling reload for each SIGHUP on the way; only a stop
+  // ends the wait early, so reloading doesn't cut the interval short
+  pub fn wait(&self, until: Instant, mut reload: impl FnMut()) {
+    loop {
+      self.sleep(until.saturating_duration_since(Instant::now()));
+      if !self.take_reload() {
+        return;
+      }
+      reload();
+    }
+  }
+}
+
+// the wait between scans: the interval plus up to jitter seconds, so a fleet of bots (or a
+// bot and cron) don't hit reddit in lockstep
+pub fn next_delay(interval_secs: u64, jitter_secs: u64) -> Duration {
+  Duration::from_secs(interval_secs + fastrand::u64(0..=jitter_secs))
+}
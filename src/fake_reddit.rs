@@ -0,0 +1,241 @@
+// An in-process stand-in for the reddit endpoints the bot uses: the oauth token, the sub
+// listing, comment trees and comment submission. Point a Config's api_url and auth_url at
+// FakeReddit::url() to run the bot end-to-end without network access.
+use serde_json::json;
+use serde_json::Value;
+use std::cmp;
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+
+#[derive(Default)]
+pub struct State {
+  // post json (the listing's "data" objects), in listing order
+  pub posts: Vec<Value>,
+  // post id -> comment json (the "data" objects), top level only
+  pub comments: HashMap<String, Vec<Value>>,
+  // (thing_id, text) for every comment submitted
+  pub replies: Vec<(String, String)>,
+  // number of token requests seen
+  pub token_requests: usize,
+  // http status to return from the comment endpoint instead of 200
+  pub reply_status: Option<u16>,
+  // error code (THREAD_LOCKED...) for the comment endpoint to turn replies down with
+  pub reply_error: Option<String>,
+  // the most posts to return in a listing page, whatever the limit asked for
+  pub page_size: Option<usize>,
+  // query string of every listing request
+  pub listing_requests: Vec<String>,
+  // user agent of the last request
+  pub user_agent: String,
+  // lifetime given to new tokens, in seconds; 3600 if not set
+  pub expires_in: Option<u64>,
+  // the token the api endpoints accept; anything else gets a 401
+  pub token: String,
+  // when set, a token request with another password gets reddit's invalid_grant error
+  pub password: Option<String>,
+}
+
+pub struct FakeReddit {
+  port: u16,
+  pub state: Arc<Mutex<State>>,
+}
+
+impl FakeReddit {
+  pub fn start() -> FakeReddit {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("couldn't bind fake reddit");
+    let port = listener.local_addr().unwrap().port();
+    let state = Arc::new(Mutex::new(State::default()));
+    let shared = Arc::clone(&state);
+    thread::spawn(move || {
+      for stream in listener.incoming().flatten() {
+        handle(stream, &shared);
+      }
+    });
+    FakeReddit{ port, state }
+  }
+
+  pub fn url(&self) -> String {
+    format!("http://127.0.0.1:{}", self.port)
+  }
+
+  pub fn add_post(&self, id: &str, title: &str, selftext: &str) {
+    self.state.lock().unwrap().posts.push(json!({
+      "id": id,
+      "name": format!("t3_{}", id),
+      "title": title,
+      "selftext": selftext,
+      "subreddit": "sub",
+    }));
+  }
+
+  pub fn add_comment(&self, post_id: &str, id: &str, body: &str) {
+    self.state.lock().unwrap().comments.entry(post_id.to_string()).or_default().push(json!({
+      "id": id,
+      "name": format!("t1_{}", id),
+      "body": body,
+      "author": "someone",
+      "link_id": format!("t3_{}", post_id),
+      "parent_id": format!("t3_{}", post_id),
+      "subreddit": "sub",
+      "replies": "",
+    }));
+  }
+
+  // as if the current token had been revoked: the next api request gets a 401
+  pub fn revoke_token(&self) {
+    self.state.lock().unwrap().token = String::new();
+  }
+
+  pub fn replies(&self) -> Vec<(String, String)> {
+    self.state.lock().unwrap().replies.clone()
+  }
+}
+
+struct Request {
+  method: String,
+  path: String,
+  user_agent: String,
+  authorization: String,
+  body: String,
+}
+
+fn read_request(stream: &TcpStream) -> Option<Request> {
+  let mut reader = BufReader::new(stream);
+  let mut line = String::new();
+  reader.read_line(&mut line).ok()?;
+  let mut parts = line.split_whitespace();
+  let method = parts.next()?.to_string();
+  let path = parts.next()?.to_string();
+
+  let mut length = 0;
+  let mut user_agent = String::new();
+  let mut authorization = String::new();
+  loop {
+    let mut header = String::new();
+    reader.read_line(&mut header).ok()?;
+    let header = header.trim_end();
+    if header.is_empty() {
+      break;
+    }
+    if let Some((name, value)) = header.split_once(':') {
+      if name.eq_ignore_ascii_case("content-length") {
+        length = value.trim().parse().unwrap_or(0);
+      } else if name.eq_ignore_ascii_case("user-agent") {
+        user_agent = value.trim().to_string();
+      } else if name.eq_ignore_ascii_case("authorization") {
+        authorization = value.trim().to_string();
+      }
+    }
+  }
+
+  let mut body = vec![0; length];
+  reader.read_exact(&mut body).ok()?;
+  Some(Request{ method, path, user_agent, authorization, body: String::from_utf8_lossy(&body).to_string() })
+}
+
+fn listing(kind: &str, children: &[Value], after: Option<&Value>) -> Value {
+  let children = children.iter().map(|data| json!({ "kind": kind, "data": data })).collect::<Vec<_>>();
+  json!({ "kind": "Listing", "data": { "after": after, "children": children } })
+}
+
+// a page of posts following the limit and after parameters, with the cursor to the next page
+fn posts_page(state: &State, query: &str) -> Value {
+  let params = parse_form(query);
+  let start = match params.get("after") {
+    Some(after) => state.posts.iter().position(|post| post["name"] == after.as_str()).map_or(0, |index| index + 1),
+    None => 0,
+  };
+  let limit = params.get("limit").and_then(|limit| limit.parse().ok()).unwrap_or(25);
+  let limit = state.page_size.map_or(limit, |size| cmp::min(size, limit));
+  let end = cmp::min(start + limit, state.posts.len());
+  let after = if end < state.posts.len() { state.posts.get(end - 1).map(|post| &post["name"]) } else { None };
+  listing("t3", &state.posts[start..end], after)
+}
+
+fn handle(mut stream: TcpStream, state: &Arc<Mutex<State>>) {
+  let request = match read_request(&stream) {
+    Some(request) => request,
+    None => return,
+  };
+  let (path, query) = request.path.split_once('?').unwrap_or((&request.path, ""));
+  let (path, query) = (path.to_string(), query.to_string());
+  let mut state = state.lock().unwrap();
+  state.user_agent = request.user_agent.to_string();
+  let authorized = request.authorization == format!("bearer {}", state.token);
+  let (status, body) = match (request.method.as_str(), path.as_str()) {
+    ("POST", "/api/v1/access_token") if state.password.as_ref().is_some_and(|password| *password != parse_form(&request.body)["password"]) => {
+      (200, json!({ "error": "invalid_grant" }))
+    },
+    ("POST", "/api/v1/access_token") => {
+      state.token_requests += 1;
+      state.token = format!("fake-token-{}", state.token_requests);
+      let expires_in = state.expires_in.unwrap_or(3600);
+      (200, json!({ "access_token": state.token, "token_type": "bearer", "expires_in": expires_in, "scope": "*" }))
+    },
+    _ if !authorized => (401, json!({ "message": "Unauthorized", "error": 401 })),
+    ("POST", "/api/comment") => {
+      let form = parse_form(&request.body);
+      match (state.reply_status, &state.reply_error) {
+        (Some(status), _) => (status, json!({ "message": "error" })),
+        (None, Some(code)) => (200, json!({ "json": { "errors": [[code, "that is not allowed", "parent"]] } })),
+        (None, None) => {
+          let id = format!("r{}", state.replies.len());
+          let field = |key: &str| form.get(key).cloned().unwrap_or_default();
+          state.replies.push((field("thing_id"), field("text")));
+          (200, json!({ "json": { "errors": [], "data": { "things": [{ "kind": "t1", "data": { "id": id, "name": format!("t1_{}", id) } }] } } }))
+        }
+      }
+    },
+    ("GET", path) if path.starts_with("/comments/") => {
+      let id = path.trim_start_matches("/comments/").trim_end_matches('/');
+      let post = state.posts.iter().filter(|post| post["id"] == id).cloned().collect::<Vec<_>>();
+      let comments = state.comments.get(id).cloned().unwrap_or_default();
+      (200, json!([listing("t3", &post, None), listing("t1", &comments, None)]))
+    },
+    ("GET", path) if path.starts_with("/r/") => {
+      state.listing_requests.push(query.to_string());
+      (200, posts_page(&state, &query))
+    },
+    _ => (404, json!({ "message": "Not Found", "error": 404 })),
+  };
+  drop(state);
+
+  let body = body.to_string();
+  let _ = write!(
+    stream,
+    "HTTP/1.1 {} Fake\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+    status, body.len(), body
+  );
+}
+
+fn parse_form(body: &str) -> HashMap<String, String> {
+  body.split('&')
+    .filter_map(|pair| pair.split_once('='))
+    .map(|(key, value)| (decode(key), decode(value)))
+    .collect()
+}
+
+fn decode(text: &str) -> String {
+  let mut bytes = Vec::new();
+  let mut chars = text.bytes();
+  while let Some(byte) = chars.next() {
+    match byte {
+      b'+' => bytes.push(b' '),
+      b'%' => {
+        let hex = [chars.next().unwrap_or(b'0'), chars.next().unwrap_or(b'0')];
+        let hex = std::str::from_utf8(&hex).unwrap_or("00");
+        bytes.push(u8::from_str_radix(hex, 16).unwrap_or(b'?'));
+      },
+      _ => bytes.push(byte),
+    }
+  }
+  String::from_utf8_lossy(&bytes).to_string()
+}
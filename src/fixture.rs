@@ -0,0 +1,220 @@
+use crate::config::Config;
+use crate::reddit::RedditApp;
+use crate::reddit::Sort;
+use crate::store::Store;
+use failure::Error;
+use failure::format_err;
+use orca::data::Comment;
+use orca::data::Listing;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::Value;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+
+// orca's Comment can't be serialised so fixtures hold this copy of the fields we use
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CommentRecord {
+  pub id: String,
+  pub name: String,
+  pub body: String,
+  pub author: String,
+  pub link_id: String,
+  pub parent_id: String,
+  pub subreddit: String,
+  #[serde(default)]
+  pub score: i64,
+  #[serde(default)]
+  pub ups: i64,
+  #[serde(default)]
+  pub downs: i64,
+  #[serde(default)]
+  pub is_submitter: bool,
+  #[serde(default)]
+  pub stickied: bool,
+  #[serde(default)]
+  pub score_hidden: bool,
+  #[serde(default)]
+  pub replies: Vec<CommentRecord>,
+}
+
+impl CommentRecord {
+  fn from_listing(listing: &Listing<Comment>) -> Vec<CommentRecord> {
+    listing.children.iter().map(CommentRecord::from).collect()
+  }
+
+  fn to_listing(records: &[CommentRecord]) -> Listing<Comment> {
+    let mut listing = Listing::<Comment>::new();
+    records.iter().for_each(|record| listing.children.push_back(record.to_comment()));
+    listing
+  }
+
+  fn to_comment(&self) -> Comment {
+    Comment{
+      edited: Option::None,
+      id: self.id.to_string(),
+      body: self.body.to_string(),
+      author: self.author.to_string(),
+      downs: self.downs,
+      is_submitter: self.is_submitter,
+      link_id: self.link_id.to_string(),
+      name: self.name.to_string(),
+      parent_id: self.parent_id.to_string(),
+      replies: CommentRecord::to_listing(&self.replies),
+      score: self.score,
+      score_hidden: self.score_hidden,
+      stickied: self.stickied,
+      subreddit: self.subreddit.to_string(),
+      ups: self.ups,
+    }
+  }
+}
+
+impl From<&Comment> for CommentRecord {
+  fn from(comment: &Comment) -> Self {
+    CommentRecord{
+      id: comment.id.to_string(),
+      name: comment.name.to_string(),
+      body: comment.body.to_string(),
+      author: comment.author.to_string(),
+      link_id: comment.link_id.to_string(),
+      parent_id: comment.parent_id.to_string(),
+      subreddit: comment.subreddit.to_string(),
+      score: comment.score,
+      ups: comment.ups,
+      downs: comment.downs,
+      is_submitter: comment.is_submitter,
+      stickied: comment.stickied,
+      score_hidden: comment.score_hidden,
+      replies: CommentRecord::from_listing(&comment.replies),
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ReplyRecord {
+  pub id: String,
+  pub rule: String,
+  pub body: String,
+  // the bot's comment, which later replies in the thread can link to
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub comment: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct Fixture {
+  // "<sub>/<sort>" -> listing
+  #[serde(default)]
+  pub posts: HashMap<String, Vec<Value>>,
+  #[serde(default)]
+  pub comment_trees: HashMap<String, Vec<CommentRecord>>,
+  #[serde(default)]
+  pub replies: Vec<ReplyRecord>,
+  // the store files the recorded run started from, by name, so a replay starts where it did
+  #[serde(default)]
+  pub store: HashMap<String, String>,
+}
+
+fn listing_key(sub: &str, sort: Sort) -> String {
+  format!("{}/{}", sub, sort)
+}
+
+impl Fixture {
+  pub fn new(json: &str) -> Result<Fixture, Error> {
+    Ok(serde_json::from_str(json)?)
+  }
+
+  pub fn load(filename: &str) -> Result<Fixture, Error> {
+    Fixture::new(&fs::read_to_string(filename)?)
+  }
+
+  pub fn save(&self, filename: &str) -> Result<(), Error> {
+    fs::write(filename, serde_json::to_string_pretty(self)?)?;
+    Ok(())
+  }
+
+  // keeps the files a run with this config reads, as they are before it starts
+  pub fn snapshot<S: Store>(&mut self, store: &S, config: &Config) {
+    let mut names = vec![config.history_filename.to_string(), config.held_filename.to_string(), config.breaker_filename.to_string()];
+    for sub in config.subs() {
+      names.extend(vec![sub.posts_db_filename, sub.comments_db_filename, sub.outbox_filename]);
+    }
+    self.store = names.into_iter().filter_map(|name| store.read(&name).map(|contents| (name, contents))).collect();
+  }
+}
+
+// passes everything through to the wrapped app, keeping a copy of the responses and replies
+pub struct Recorder<T: RedditApp> {
+  pub inner: T,
+  pub fixture: RefCell<Fixture>,
+}
+
+impl<T: RedditApp> Recorder<T> {
+  pub fn new(inner: T) -> Recorder<T> {
+    Recorder{ inner, fixture: RefCell::new(Fixture::default()) }
+  }
+
+  pub fn save(&self, filename: &str) -> Result<(), Error> {
+    self.fixture.borrow().save(filename)
+  }
+}
+
+impl<T: RedditApp> RedditApp for Recorder<T> {
+  fn get_comment_tree(&self, post_id: &str) -> Result<Listing<Comment>, Error> {
+    let listing = self.inner.get_comment_tree(post_id)?;
+    self.fixture.borrow_mut().comment_trees.insert(post_id.to_string(), CommentRecord::from_listing(&listing));
+    Ok(listing)
+  }
+  fn get_posts(&self, sub: &str, sort: Sort, depth: usize) -> Result<Vec<Value>, Error> {
+    let posts = self.inner.get_posts(sub, sort, depth)?;
+    self.fixture.borrow_mut().posts.entry(listing_key(sub, sort)).or_default().extend(posts.iter().cloned());
+    Ok(posts)
+  }
+  fn reply(&self, id: &str, rule: &str, body: &str) -> Result<Option<String>, Error> {
+    let comment = self.inner.reply(id, rule, body)?;
+    self.fixture.borrow_mut().replies.push(ReplyRecord{ id: id.to_string(), rule: rule.to_string(), body: body.to_string(), comment: comment.clone() });
+    Ok(comment)
+  }
+}
+
+// serves a recorded fixture back and collects the replies that would have been made; run it
+// with a MemoryStore holding the fixture's store, so it starts from the recorded state
+pub struct Replayer {
+  pub fixture: Fixture,
+  pub replies: RefCell<Vec<ReplyRecord>>,
+}
+
+impl Replayer {
+  pub fn new(fixture: Fixture) -> Replayer {
+    Replayer{ fixture, replies: RefCell::new(Vec::new()) }
+  }
+
+  pub fn load(filename: &str) -> Result<Replayer, Error> {
+    Ok(Replayer::new(Fixture::load(filename)?))
+  }
+
+  // true when the replay made exactly the replies that were recorded
+  pub fn matches_recording(&self) -> bool {
+    *self.replies.borrow() == self.fixture.replies
+  }
+}
+
+impl RedditApp for Replayer {
+  fn get_comment_tree(&self, post_id: &str) -> Result<Listing<Comment>, Error> {
+    match self.fixture.comment_trees.get(post_id) {
+      Some(records) => Ok(CommentRecord::to_listing(records)),
+      None => Err(format_err!("No recorded comment tree for post {}", post_id)),
+    }
+  }
+  fn get_posts(&self, sub: &str, sort: Sort, depth: usize) -> Result<Vec<Value>, Error> {
+    let posts = self.fixture.posts.get(&listing_key(sub, sort)).cloned().unwrap_or_default();
+    Ok(posts.into_iter().take(depth).collect())
+  }
+  // answers with the comment recorded for the same reply, if there was one
+  fn reply(&self, id: &str, rule: &str, body: &str) -> Result<Option<String>, Error> {
+    let comment = self.fixture.replies.iter().find(|reply| reply.id == id).and_then(|reply| reply.comment.clone());
+    self.replies.borrow_mut().push(ReplyRecord{ id: id.to_string(), rule: rule.to_string(), body: body.to_string(), comment: comment.clone() });
+    Ok(comment)
+  }
+}
@@ -0,0 +1,59 @@
+use crate::reddit::Circuit;
+use serde_json::json;
+use serde_json::Value;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+lazy_static! {
+  static ref HEALTH: Arc<Mutex<Health>> = Arc::new(Mutex::new(Health::default()));
+}
+
+// what /healthz reports, updated as the daemon runs
+#[derive(Clone, Default, Debug)]
+pub struct Health {
+  // unix seconds; a daemon that hasn't finished a scan yet is judged from when it started
+  pub started: u64,
+  pub last_scan: Option<u64>,
+  // why the last scan failed, cleared by the next one to succeed
+  pub last_error: Option<String>,
+  // None until the first token request; Err holds why the last one failed
+  pub authenticated: Option<Result<(), String>>,
+  pub circuit: Circuit,
+  pub outbox: u32,
+  // a last successful scan older than this is unhealthy
+  pub max_age_secs: u64,
+}
+
+impl Health {
+  // whether the bot is healthy at now, and the report to serve
+  pub fn report(&self, now: u64) -> (bool, Value) {
+    let age = now.saturating_sub(self.last_scan.unwrap_or(self.started));
+    let healthy = age <= self.max_age_secs;
+    let authenticated = match &self.authenticated {
+      None => json!(null),
+      Some(Ok(())) => json!(true),
+      Some(Err(_)) => json!(false),
+    };
+    let report = json!({
+      "status": if healthy { "ok" } else { "stale" },
+      "last_scan": self.last_scan,
+      "last_scan_age_secs": age,
+      "max_age_secs": self.max_age_secs,
+      "last_error": self.last_error,
+      "authenticated": authenticated,
+      "auth_error": self.authenticated.as_ref().and_then(|result| result.as_ref().err()),
+      "circuit": self.circuit.to_string(),
+      "outbox": self.outbox,
+    });
+    (healthy, report)
+  }
+}
+
+pub fn update(change: impl FnOnce(&mut Health)) {
+  change(&mut HEALTH.lock().unwrap());
+}
+
+// the health the daemon updates, for the server to report
+pub fn shared() -> Arc<Mutex<Health>> {
+  Arc::clone(&HEALTH)
+}
@@ -1,87 +1,287 @@
-use std::fs::File;
-use std::collections::HashSet;
-use crate::reddit::RedditApp;
+use crate::bot::Bot;
+use crate::breaker::Breaker;
+use crate::breaker::CircuitOpen;
+use crate::config::Config;
+use crate::config::ConfigError;
+use crate::daemon::Signals;
+use crate::fixture::Recorder;
+use crate::fixture::Replayer;
+use crate::lock::Lock;
+use crate::lock::Locked;
+use crate::reddit::AuthFailed;
+use crate::reddit::Circuit;
+use crate::reddit::DryRun;
 use crate::reddit::Reddit;
-use crate::post::Post;
+use crate::reddit::RedditApp;
+use crate::reddit::Unavailable;
+use crate::store::FileStore;
+use crate::store::MemoryStore;
+use crate::store::Store;
+use failure::bail;
 use failure::Error;
-use std::cmp;
-use std::fs;
-use std::io::Write;
+use log::error;
+use log::info;
+use log::warn;
+use std::env;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Instant;
 
 mod post;
 mod config;
 mod reddit;
+mod outbox;
+mod fixture;
+mod store;
+mod bot;
+mod validate;
+mod daemon;
+mod lock;
+mod throttle;
+mod blackout;
+mod breaker;
+mod logging;
+mod metrics;
+mod health;
+mod server;
+mod summary;
 
 #[macro_use]
 extern crate lazy_static;
 
-fn get_db(filename: &str) -> HashSet<String> {
-  match fs::read_to_string(&filename) {
-    Ok(contents) => contents
-    .lines()
-    .map(|x| x.to_string())
-    .collect::<HashSet<_>>(),
-    Err(_e) => HashSet::new(),
+pub use crate::summary::Summary;
+
+// exit statuses, after sysexits.h where one fits; main's --help lists them
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_ERROR: i32 = 1;
+// the run finished, but some replies or listings failed; failed replies are queued for retry
+pub const EXIT_PARTIAL: i32 = 3;
+pub const EXIT_USAGE: i32 = 64;
+// reddit couldn't be reached, no listing could be read or the circuit breaker opened
+pub const EXIT_UNAVAILABLE: i32 = 69;
+// another rrbot holds the lock: EX_TEMPFAIL, "try again later"
+pub const EXIT_LOCKED: i32 = 75;
+// reddit turned the credentials away
+pub const EXIT_AUTH: i32 = 77;
+pub const EXIT_CONFIG: i32 = 78;
+
+// options that can't be used together
+#[derive(Debug)]
+pub struct Usage(pub String);
+
+impl fmt::Display for Usage {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{} (see rrbot --help)", self.0)
   }
 }
 
-fn write_db(filename: &str, db: &HashSet<String>) {
-  let file = match File::create(filename) {
-    Err(why) => panic!("couldn't create file: {}", why),
-    Ok(file) => file,
-  };
+impl std::error::Error for Usage {}
 
-  db.into_iter().for_each(|x| {
-      writeln!(&file, "{}", x).expect("couldn't write to file");
-  });
+pub fn exit_code(e: &Error) -> i32 {
+  if e.downcast_ref::<Usage>().is_some() {
+    EXIT_USAGE
+  } else if e.downcast_ref::<Locked>().is_some() {
+    EXIT_LOCKED
+  } else if e.downcast_ref::<ConfigError>().is_some() {
+    EXIT_CONFIG
+  } else if e.downcast_ref::<AuthFailed>().is_some() {
+    EXIT_AUTH
+  } else if e.downcast_ref::<Unavailable>().is_some() || e.downcast_ref::<CircuitOpen>().is_some() {
+    EXIT_UNAVAILABLE
+  } else {
+    EXIT_ERROR
+  }
 }
 
-pub fn run() -> Result<(), Error> {
-  let app = Reddit::new();
-
-  // get existing replied to posts, comments and inbox replies
-  let mut comments_db = get_db(&app.config.comments_db_filename);
-  let mut posts_db = get_db(&app.config.posts_db_filename);
-  //let mut inbox_db = get_db(&app.config.inbox_db_filename);
-
-  // get new posts and check for post and comment matches
-  let posts = app.get_posts();
-  let count = cmp::min(posts.len(), app.config.hot_take as usize);
-  for json in &posts[0..count] {
-    let post = Post::new(&json["data"].to_string(), &app)?;
-    if post.is_match()? {
-      if !posts_db.contains(&post.id) {
-        println!("Replying to post {}", post.id);
-        match app.reply(&post.id) {
-          Ok(()) => posts_db.insert(post.id.to_string()),
-          Err(e) => {
-            println!("Error replying to post {}: {}", post.id, e);
-            false
-          }
-        };
-      }
+// the status of a run that returned Ok: partial if anything in it failed
+pub fn summary_exit_code(summary: Option<&Summary>) -> i32 {
+  match summary {
+    Some(summary) if !summary.errors.is_empty() => EXIT_PARTIAL,
+    _ => EXIT_OK,
+  }
+}
+
+#[derive(Default)]
+pub struct Options {
+  // defaults to $RRBOT_CONFIG, then config.json
+  pub config: Option<String>,
+  // (field, value) pairs from the command line, applied over the file and environment
+  pub overrides: Vec<(String, String)>,
+  pub print_config: bool,
+  // `rrbot config check`: validate the config and exit
+  pub check_config: bool,
+  pub dry_run: bool,
+  // write the responses and replies of this run to a fixture file
+  pub record: Option<String>,
+  // serve responses from a fixture file instead of reddit
+  pub replay: Option<String>,
+  // `rrbot daemon`: scan every interval_secs until stopped
+  pub daemon: bool,
+}
+
+pub fn run(options: &Options) -> Result<Option<Summary>, Error> {
+  logging::init();
+  match (options.daemon, &options.record, &options.replay) {
+    (true, Some(_), _) | (true, _, Some(_)) => return Err(Usage("--record and --replay can't be used with rrbot daemon".to_string()).into()),
+    (_, Some(_), Some(_)) => return Err(Usage("--record and --replay can't be used together".to_string()).into()),
+    _ => (),
+  }
+  let path = match &options.config {
+    Some(path) => path.to_string(),
+    None => env::var("RRBOT_CONFIG").unwrap_or_else(|_| config::DEFAULT_PATH.to_string()),
+  };
+  let config = Config::load(&path, env::vars(), &options.overrides)?;
+  logging::configure(&config);
+  if options.check_config {
+    println!("{}: OK", path);
+    return Ok(None);
+  }
+  if options.print_config {
+    println!("{}", config.redacted());
+    return Ok(None);
+  }
+
+  // taken before any store is read and held until the run (or daemon) ends
+  let _lock = Lock::acquire(&config.lock_filename)?;
+
+  if options.daemon {
+    let signals = Signals::register()?;
+    if options.dry_run {
+      info!("Dry run: replies are logged, not posted");
+      return daemon(|config| Ok(DryRun::new(Reddit::new(config)?)), config, &path, options, &signals).map(|_| None);
+    }
+    return daemon(Reddit::new, config, &path, options, &signals).map(|_| None);
+  }
+
+  // starts from the stores as the recording found them, kept in memory so the live files are
+  // neither read nor written
+  if let Some(filename) = &options.replay {
+    let replayer = Replayer::load(filename)?;
+    let store = MemoryStore{ files: replayer.fixture.store.clone() };
+    let mut bot = Bot::new(replayer, store, config);
+    let summary = bot.run()?;
+    if !bot.app.matches_recording() {
+      bail!("Replies differ from those recorded in {}", filename);
     }
-    post.get_matching_comments()
-        .filter(|id| !comments_db.contains(id)).collect::<Vec<_>>()
-        .iter_mut()
-        .for_each(|id| {
-          println!("Replying to comment {}", id);
-          match app.reply(id) {
-            Ok(()) => comments_db.insert(id.to_string()),
-          Err(e) => {
-            println!("Error replying to comment {}: {}", id, e);
-            false
-          }
-          };
-        });
+    return Ok(Some(summary));
   }
 
-  write_db(&app.config.posts_db_filename, &posts_db);
-  write_db(&app.config.comments_db_filename, &comments_db);
-  //write_db(&app.config.inbox_db_filename, &inbox_db);
+  // a run that can't even start (say the credentials are turned away) still leaves its
+  // metrics, the token request included, for the textfile collector
+  let app = match Reddit::new(&config) {
+    Ok(app) => app,
+    Err(e) => {
+      write_textfile(&config);
+      return Err(e);
+    },
+  };
+  if options.dry_run {
+    info!("Dry run: replies are logged, not posted");
+    return record_or_run(DryRun::new(app), config, options).map(Some);
+  }
+  record_or_run(app, config, options).map(Some)
+}
 
+// scans, then sleeps until the next scan is due, until SIGTERM or SIGINT; SIGHUP reloads
+// the config between scans, keeping the current one if the new one doesn't load
+fn daemon<T, F>(connect: F, config: Config, path: &str, options: &Options, signals: &Signals) -> Result<(), Error>
+where T: RedditApp, F: Fn(&Config) -> Result<T, Error>
+{
+  // the port is bound for the life of the process, so a reload keeps the first one
+  if let Some(port) = config.metrics_port {
+    server::serve(port, health::shared())?;
+  }
+  health::update(|health| {
+    health.started = outbox::now();
+    health.max_age_secs = config.health_max_age();
+  });
+  let threshold = config.breaker_threshold;
+  let mut bot = Bot::new(Breaker::new(connect(&config)?, threshold), FileStore, config);
+  info!("Daemon started: scanning every {}s (+ up to {}s)", bot.config.interval_secs, bot.config.jitter_secs);
+  while !signals.stopping() {
+    bot.dry_run = options.dry_run;
+    bot.stop = Arc::clone(&signals.stop);
+    // a failed scan is retried at the next interval rather than ending the daemon
+    if let Err(e) = run_once(&mut bot) {
+      error!("Error scanning: {}", e);
+    }
+    let next_scan = Instant::now() + daemon::next_delay(bot.config.interval_secs, bot.config.jitter_secs);
+    signals.wait(next_scan, || {
+      info!("Reloading {}", path);
+      match Config::load(path, env::vars(), &options.overrides).and_then(|config| Ok((connect(&config)?, config))) {
+        Ok((app, config)) => {
+          logging::configure(&config);
+          health::update(|health| health.max_age_secs = config.health_max_age());
+          bot = Bot::new(Breaker::new(app, config.breaker_threshold), FileStore, config);
+        },
+        Err(e) => warn!("Keeping the current config: {}", e),
+      }
+    });
+  }
+  info!("Daemon stopped");
   Ok(())
 }
 
+fn record_or_run<T: RedditApp>(app: T, config: Config, options: &Options) -> Result<Summary, Error> {
+  let threshold = config.breaker_threshold;
+  match &options.record {
+    Some(filename) => {
+      let mut bot = Bot::new(Breaker::new(Recorder::new(app), threshold), FileStore, config);
+      bot.dry_run = options.dry_run;
+      bot.app.inner.fixture.borrow_mut().snapshot(&bot.store, &bot.config);
+      let result = run_once(&mut bot);
+      bot.app.inner.save(filename)?;
+      result
+    },
+    None => {
+      let mut bot = Bot::new(Breaker::new(app, threshold), FileStore, config);
+      bot.dry_run = options.dry_run;
+      run_once(&mut bot)
+    },
+  }
+}
+
+fn write_textfile(config: &Config) {
+  if let Some(path) = &config.metrics_textfile {
+    if let Err(e) = metrics::write_textfile(path) {
+      warn!("{}", e);
+    }
+  }
+}
+
+// one run, failing with the breaker's summary if it opened along the way (or the auth error,
+// if that's what opened it), or if nothing could be read; the metrics textfile and the health
+// report are updated whether or not it succeeded
+fn run_once<T: RedditApp, S: Store>(bot: &mut Bot<Breaker<T>, S>) -> Result<Summary, Error> {
+  // the breaker carries on from the last run, so a breaker that opened in one cron run still
+  // lets only one call through in the next
+  let result = bot.app.load(&bot.store, &bot.config.breaker_filename).and_then(|_| bot.run()).and_then(|summary| match bot.app.circuit() {
+    Circuit::Open => Err(bot.app.auth_error().map_or_else(|| bot.app.summary().into(), Error::from)),
+    _ if summary.nothing_read() => Err(Unavailable(format!("no listing could be read ({} errors)", summary.errors["listing"])).into()),
+    _ => Ok(summary),
+  });
+  if !bot.dry_run {
+    if let Err(e) = bot.app.save(&mut bot.store, &bot.config.breaker_filename) {
+      warn!("Error saving the circuit breaker state: {}", e);
+    }
+  }
+  write_textfile(&bot.config);
+  let circuit = bot.app.circuit();
+  health::update(|health| {
+    health.circuit = circuit;
+    match &result {
+      Ok(summary) => {
+        health.last_scan = Some(outbox::now());
+        health.last_error = None;
+        health.outbox = summary.queued;
+      },
+      Err(e) => health.last_error = Some(e.to_string()),
+    }
+  });
+  result
+}
+
+#[cfg(test)]
+mod fake_reddit;
 #[cfg(test)]
 mod test;
\ No newline at end of file
@@ -0,0 +1,76 @@
+use failure::Error;
+use failure::format_err;
+use std::fmt;
+use std::fs;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::process;
+
+// held for as long as a run (or the daemon) uses the stores. What keeps other runs out is an
+// OS lock on the file, which goes with the process however it ends; the pid written into the
+// file only says who holds it
+pub struct Lock {
+  file: File,
+}
+
+// another process holds the lock; pid is None if it hasn't written its pid yet
+#[derive(Debug)]
+pub struct Locked {
+  pub path: String,
+  pub pid: Option<u32>,
+}
+
+impl fmt::Display for Locked {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self.pid {
+      Some(pid) => write!(f, "Another rrbot (pid {}) is running; it holds {}", pid, self.path),
+      None => write!(f, "Another rrbot is running; it holds {}", self.path),
+    }
+  }
+}
+
+impl std::error::Error for Locked {}
+
+impl Lock {
+  pub fn acquire(path: &str) -> Result<Lock, Error> {
+    let error = |e: std::io::Error| format_err!("Error locking {}: {}", path, e);
+    // the file is never removed, so every run locks the same one, and isn't truncated until
+    // it's locked, so the holder's pid survives
+    let mut file = OpenOptions::new().write(true).create(true).truncate(false).open(path).map_err(error)?;
+    if !try_lock(&file).map_err(error)? {
+      let pid = fs::read_to_string(path).ok().and_then(|pid| pid.trim().parse::<u32>().ok());
+      return Err(Locked{ path: path.to_string(), pid }.into());
+    }
+    file.set_len(0).and_then(|_| writeln!(file, "{}", process::id())).map_err(error)?;
+    Ok(Lock{ file })
+  }
+}
+
+impl Drop for Lock {
+  // the OS lock is released when the file is closed; the pid is cleared first so the file
+  // doesn't name a process that has finished
+  fn drop(&mut self) {
+    let _ = self.file.set_len(0);
+  }
+}
+
+// true if the lock was taken, false if another open of the file holds it
+#[cfg(unix)]
+fn try_lock(file: &File) -> std::io::Result<bool> {
+  use std::os::unix::io::AsRawFd;
+  if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+    return Ok(true);
+  }
+  let error = std::io::Error::last_os_error();
+  match error.raw_os_error() {
+    Some(libc::EWOULDBLOCK) => Ok(false),
+    _ => Err(error),
+  }
+}
+
+// no advisory locks to take, so runs aren't kept apart
+#[cfg(not(unix))]
+fn try_lock(_file: &File) -> std::io::Result<bool> {
+  Ok(true)
+}
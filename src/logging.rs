@@ -0,0 +1,156 @@
+use crate::config::Config;
+use crate::config::LogFormat;
+use log::Level;
+use log::LevelFilter;
+use log::Log;
+use log::Metadata;
+use log::Record;
+use serde_json::json;
+use std::cell::RefCell;
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::Once;
+use std::sync::RwLock;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+lazy_static! {
+  static ref LOGGER: Logger = Logger{ settings: RwLock::new((Filter::default(), LogFormat::Text)) };
+}
+
+static INSTALL: Once = Once::new();
+
+thread_local! {
+  // (field, value) pairs added to every line logged while they're in scope
+  static CONTEXT: RefCell<Vec<(&'static str, String)>> = const { RefCell::new(Vec::new()) };
+}
+
+// a default level plus levels for particular modules: "info" or "warn,reddit=debug,ureq=info".
+// Modules are ours (reddit, rrbot::reddit) or another crate's (ureq); the longest match wins
+#[derive(Clone, PartialEq, Debug)]
+pub struct Filter {
+  pub default: LevelFilter,
+  pub modules: Vec<(String, LevelFilter)>,
+}
+
+impl Default for Filter {
+  fn default() -> Filter {
+    Filter{ default: LevelFilter::Info, modules: Vec::new() }
+  }
+}
+
+impl Filter {
+  pub fn parse(spec: &str) -> Result<Filter, String> {
+    let mut filter = Filter{ default: LevelFilter::Info, modules: Vec::new() };
+    for part in spec.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+      match part.split_once('=') {
+        Some((module, level)) => filter.modules.push((module.trim().to_string(), parse_level(level.trim())?)),
+        None => filter.default = parse_level(part)?,
+      }
+    }
+    Ok(filter)
+  }
+
+  pub fn level(&self, target: &str) -> LevelFilter {
+    let matches = |module: &str| {
+      let ours = format!("rrbot::{}", module);
+      [module, ours.as_str()].iter().any(|module| target == *module || target.starts_with(&format!("{}::", module)))
+    };
+    self.modules.iter()
+      .filter(|(module, _)| matches(module))
+      .max_by_key(|(module, _)| module.len())
+      .map_or(self.default, |(_, level)| *level)
+  }
+
+  // the most verbose level any module logs at
+  fn max(&self) -> LevelFilter {
+    self.modules.iter().map(|(_, level)| *level).fold(self.default, std::cmp::max)
+  }
+}
+
+fn parse_level(level: &str) -> Result<LevelFilter, String> {
+  LevelFilter::from_str(level).map_err(|_| format!("unknown level \"{}\", expected one of off, error, warn, info, debug, trace", level))
+}
+
+struct Logger {
+  settings: RwLock<(Filter, LogFormat)>,
+}
+
+impl Log for Logger {
+  fn enabled(&self, metadata: &Metadata) -> bool {
+    let settings = self.settings.read().unwrap();
+    metadata.level() <= settings.0.level(metadata.target())
+  }
+
+  fn log(&self, record: &Record) {
+    if !self.enabled(record.metadata()) {
+      return;
+    }
+    let format = self.settings.read().unwrap().1;
+    let line = format_line(format, record.level(), record.target(), &record.args().to_string(), &fields(), now());
+    // warnings and errors go to stderr, where a supervisor or cron mail picks them out; the rest
+    // to stdout
+    let _ = match record.level() {
+      Level::Error | Level::Warn => writeln!(std::io::stderr(), "{}", line),
+      _ => writeln!(std::io::stdout(), "{}", line),
+    };
+  }
+
+  fn flush(&self) {
+    let _ = std::io::stdout().flush();
+  }
+}
+
+// installs the logger at info, as text, until the config says otherwise; safe to call twice
+pub fn init() {
+  INSTALL.call_once(|| {
+    if log::set_logger(&*LOGGER).is_ok() {
+      log::set_max_level(LevelFilter::Info);
+    }
+  });
+}
+
+// applies the config's log_level and log_format; validation has already checked them
+pub fn configure(config: &Config) {
+  let filter = Filter::parse(&config.log_level).unwrap_or_default();
+  log::set_max_level(filter.max());
+  *LOGGER.settings.write().unwrap() = (filter, config.log_format);
+}
+
+// adds field to the lines logged on this thread until the returned guard is dropped
+pub fn context(field: &'static str, value: &str) -> Context {
+  CONTEXT.with(|context| context.borrow_mut().push((field, value.to_string())));
+  Context
+}
+
+pub struct Context;
+
+impl Drop for Context {
+  fn drop(&mut self) {
+    CONTEXT.with(|context| context.borrow_mut().pop());
+  }
+}
+
+// the context fields in scope, innermost last
+pub fn fields() -> Vec<(&'static str, String)> {
+  CONTEXT.with(|context| context.borrow().clone())
+}
+
+// text is the level and message, as a person reads it; json is one object per line with the
+// time, level, module, message and the context fields, for a log aggregator
+pub fn format_line(format: LogFormat, level: Level, target: &str, message: &str, fields: &[(&str, String)], time: f64) -> String {
+  match format {
+    LogFormat::Text => format!("{:<5} {}", level, message),
+    LogFormat::Json => {
+      let mut line = json!({ "time": time, "level": level.as_str().to_lowercase(), "module": target, "message": message });
+      for (field, value) in fields {
+        line[*field] = json!(value);
+      }
+      line.to_string()
+    },
+  }
+}
+
+fn now() -> f64 {
+  SystemTime::now().duration_since(UNIX_EPOCH).map(|now| now.as_secs_f64()).unwrap_or_default()
+}
@@ -1,8 +1,82 @@
+use std::env;
 use std::process;
 
+fn help() -> String {
+  format!(
+    "Usage: rrbot [daemon | config check] [options]
+
+Answers questions in subreddits, once or (with daemon) every interval_secs.
+
+Options:
+  --config <path>       the config file (default $RRBOT_CONFIG, then config.json)
+  --print-config        print the effective config, secrets redacted, and exit
+  --dry-run             log the replies instead of posting them
+  --record <path>       save reddit's responses and the replies to a fixture file
+  --replay <path>       run against a fixture file instead of reddit
+  --<field> <value>     set a config field, e.g. --sub bodyweightfitness or --hot-take 25
+  -h, --help            print this and exit
+
+Exit status:
+  {:<3} the run finished and nothing failed
+  {:<3} some replies, listings or posts' comments failed; failed replies are queued
+      for the next run, unless reddit rejected them for good
+  {:<3} the config couldn't be read, parsed or validated
+  {:<3} reddit turned the credentials away
+  {:<3} reddit couldn't be reached, no listing could be read or the circuit breaker opened
+  {:<3} another rrbot holds the lock file
+  {:<3} bad arguments
+  {:<3} any other error",
+    rrbot::EXIT_OK, rrbot::EXIT_PARTIAL, rrbot::EXIT_CONFIG, rrbot::EXIT_AUTH,
+    rrbot::EXIT_UNAVAILABLE, rrbot::EXIT_LOCKED, rrbot::EXIT_USAGE, rrbot::EXIT_ERROR,
+  )
+}
+
+fn value(flag: &str, args: &mut impl Iterator<Item=String>) -> String {
+  args.next().unwrap_or_else(|| {
+    eprintln!("Missing value for {}", flag);
+    process::exit(rrbot::EXIT_USAGE);
+  })
+}
+
 fn main() {
-  if let Err(e) = rrbot::run() {
-    eprintln!("Application error: {}", e);
-    process::exit(1);
+  let mut options = rrbot::Options::default();
+  let mut args = env::args().skip(1);
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "-h" | "--help" => {
+        println!("{}", help());
+        return;
+      },
+      "--dry-run" => options.dry_run = true,
+      "--record" => options.record = Some(value(&arg, &mut args)),
+      "--replay" => options.replay = Some(value(&arg, &mut args)),
+      "--config" => options.config = Some(value(&arg, &mut args)),
+      "--print-config" => options.print_config = true,
+      "daemon" => options.daemon = true,
+      "config" => match args.next().as_deref() {
+        Some("check") => options.check_config = true,
+        _ => {
+          eprintln!("Usage: rrbot config check");
+          process::exit(rrbot::EXIT_USAGE);
+        }
+      },
+      // anything else is a config field, e.g. --sub bodyweightfitness or --hot-take 25
+      flag if flag.starts_with("--") => {
+        let field = flag.trim_start_matches("--").replace('-', "_");
+        options.overrides.push((field, value(&arg, &mut args)));
+      },
+      _ => {
+        eprintln!("Unknown argument: {} (see rrbot --help)", arg);
+        process::exit(rrbot::EXIT_USAGE);
+      }
+    }
+  }
+
+  match rrbot::run(&options) {
+    Ok(summary) => process::exit(rrbot::summary_exit_code(summary.as_ref())),
+    Err(e) => {
+      log::error!("Application error: {}", e);
+      process::exit(rrbot::exit_code(&e));
+    },
   }
 }
@@ -0,0 +1,159 @@
+use failure::Error;
+use failure::format_err;
+use std::collections::BTreeMap;
+use std::fs;
+use std::sync::Mutex;
+use std::time::Instant;
+
+lazy_static! {
+  static ref REGISTRY: Mutex<Registry> = Mutex::new(Registry::default());
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Type {
+  Counter,
+  Gauge,
+  Histogram,
+}
+
+// every metric the bot exports: name, type and help text
+pub const METRICS: &[(&str, Type, &str)] = &[
+  ("rrbot_api_requests_total", Type::Counter, "Reddit API requests by endpoint and HTTP status (error when there was no response)."),
+  ("rrbot_api_request_duration_seconds", Type::Histogram, "Reddit API request latency by endpoint."),
+  ("rrbot_matches_total", Type::Counter, "Posts and comments matching a rule that weren't already answered or queued."),
+  ("rrbot_replies_total", Type::Counter, "Replies sent by rule, including retries from the outbox."),
+  ("rrbot_reply_errors_total", Type::Counter, "Replies that failed by rule, including failed retries."),
+  ("rrbot_store_entries", Type::Gauge, "Entries in each store as last written, by sub where the store is per sub."),
+  ("rrbot_scan_duration_seconds", Type::Histogram, "How long each run or daemon scan took."),
+  ("rrbot_last_scan_timestamp_seconds", Type::Gauge, "When the last run or daemon scan finished, as a unix time."),
+];
+
+const API_BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+const SCAN_BUCKETS: &[f64] = &[1.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0];
+
+#[derive(Clone, Default, Debug)]
+pub struct Histogram {
+  // cumulative count per upper bound, as prometheus wants them
+  buckets: Vec<(f64, u64)>,
+  count: u64,
+  sum: f64,
+}
+
+impl Histogram {
+  fn new(bounds: &[f64]) -> Histogram {
+    Histogram{ buckets: bounds.iter().map(|bound| (*bound, 0)).collect(), count: 0, sum: 0.0 }
+  }
+
+  fn observe(&mut self, value: f64) {
+    self.buckets.iter_mut().filter(|(bound, _)| value <= *bound).for_each(|(_, count)| *count += 1);
+    self.count += 1;
+    self.sum += value;
+  }
+}
+
+// metric name -> rendered labels -> value
+#[derive(Default)]
+pub struct Registry {
+  values: BTreeMap<&'static str, BTreeMap<String, f64>>,
+  histograms: BTreeMap<&'static str, BTreeMap<String, Histogram>>,
+}
+
+impl Registry {
+  pub fn inc(&mut self, name: &'static str, labels: &[(&str, &str)]) {
+    *self.values.entry(name).or_default().entry(render_labels(labels)).or_default() += 1.0;
+  }
+
+  pub fn set(&mut self, name: &'static str, labels: &[(&str, &str)], value: f64) {
+    self.values.entry(name).or_default().insert(render_labels(labels), value);
+  }
+
+  pub fn observe(&mut self, name: &'static str, labels: &[(&str, &str)], value: f64) {
+    let bounds = if name == "rrbot_scan_duration_seconds" { SCAN_BUCKETS } else { API_BUCKETS };
+    self.histograms.entry(name).or_default()
+      .entry(render_labels(labels))
+      .or_insert_with(|| Histogram::new(bounds))
+      .observe(value);
+  }
+
+  // the prometheus text format; metrics with nothing recorded yet are left out
+  pub fn render(&self) -> String {
+    let mut text = String::new();
+    for (name, kind, help) in METRICS {
+      let (values, histograms) = (self.values.get(name), self.histograms.get(name));
+      if values.is_none() && histograms.is_none() {
+        continue;
+      }
+      let kind = match kind {
+        Type::Counter => "counter",
+        Type::Gauge => "gauge",
+        Type::Histogram => "histogram",
+      };
+      text += &format!("# HELP {} {}\n# TYPE {} {}\n", name, help, name, kind);
+      for (labels, value) in values.into_iter().flatten() {
+        text += &format!("{}{} {}\n", name, braces(labels), value);
+      }
+      for (labels, histogram) in histograms.into_iter().flatten() {
+        let bucket = |le: &str| match labels.is_empty() {
+          true => format!("{{le=\"{}\"}}", le),
+          false => format!("{{{},le=\"{}\"}}", labels, le),
+        };
+        for (bound, count) in &histogram.buckets {
+          text += &format!("{}_bucket{} {}\n", name, bucket(&bound.to_string()), count);
+        }
+        text += &format!("{}_bucket{} {}\n", name, bucket("+Inf"), histogram.count);
+        text += &format!("{}_sum{} {}\n", name, braces(labels), histogram.sum);
+        text += &format!("{}_count{} {}\n", name, braces(labels), histogram.count);
+      }
+    }
+    text
+  }
+}
+
+fn render_labels(labels: &[(&str, &str)]) -> String {
+  let escape = |value: &str| value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+  labels.iter().map(|(name, value)| format!("{}=\"{}\"", name, escape(value))).collect::<Vec<_>>().join(",")
+}
+
+fn braces(labels: &str) -> String {
+  if labels.is_empty() { String::new() } else { format!("{{{}}}", labels) }
+}
+
+pub fn inc(name: &'static str, labels: &[(&str, &str)]) {
+  REGISTRY.lock().unwrap().inc(name, labels);
+}
+
+pub fn set(name: &'static str, labels: &[(&str, &str)], value: f64) {
+  REGISTRY.lock().unwrap().set(name, labels, value);
+}
+
+pub fn observe(name: &'static str, labels: &[(&str, &str)], value: f64) {
+  REGISTRY.lock().unwrap().observe(name, labels, value);
+}
+
+pub fn render() -> String {
+  REGISTRY.lock().unwrap().render()
+}
+
+// counts a reddit request and times it from start; the status is the response's, or the
+// one ureq reports an error with, or error when there was no response at all
+pub fn api_request<T>(endpoint: &str, response: &Result<T, Error>, status: impl Fn(&T) -> u16, start: Instant) {
+  let status = match response {
+    Ok(response) => status(response).to_string(),
+    Err(e) => match e.downcast_ref::<ureq::Error>() {
+      Some(ureq::Error::Status(code, _)) => code.to_string(),
+      _ => "error".to_string(),
+    },
+  };
+  let mut registry = REGISTRY.lock().unwrap();
+  registry.inc("rrbot_api_requests_total", &[("endpoint", endpoint), ("status", &status)]);
+  registry.observe("rrbot_api_request_duration_seconds", &[("endpoint", endpoint)], start.elapsed().as_secs_f64());
+}
+
+// for the node exporter's textfile collector, which reads *.prom files and may do so at any
+// moment, so the file is written alongside and renamed into place
+pub fn write_textfile(path: &str) -> Result<(), Error> {
+  let partial = format!("{}.partial", path);
+  fs::write(&partial, render())
+    .and_then(|_| fs::rename(&partial, path))
+    .map_err(|e| format_err!("Error writing metrics to {}: {}", path, e))
+}
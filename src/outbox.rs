@@ -0,0 +1,208 @@
+use crate::blackout::Blackout;
+use crate::logging;
+use crate::metrics;
+use crate::post::REPLY;
+use crate::post::RULE;
+use crate::reddit;
+use crate::reddit::Circuit;
+use crate::reddit::RedditApp;
+use crate::store::Store;
+use crate::throttle::History;
+use crate::throttle::Limits;
+use crate::throttle::Reply;
+use failure::Error;
+use log::error;
+use log::info;
+use log::warn;
+use serde::Deserialize;
+use serde::Serialize;
+use std::cmp;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+  Post,
+  Comment,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Entry {
+  pub id: String,
+  pub kind: Kind,
+  // the post the reply goes in, its flair and whose post or comment it answers, for the
+  // blackouts and reply limits; empty in entries queued before they were kept
+  #[serde(default)]
+  pub thread: String,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub flair: Option<String>,
+  #[serde(default)]
+  pub author: String,
+  #[serde(default = "default_rule")]
+  pub rule: String,
+  #[serde(default = "default_body")]
+  pub body: String,
+  pub attempts: u32,
+  pub last_error: String,
+  pub next_attempt: u64,
+}
+
+fn default_rule() -> String { RULE.to_string() }
+fn default_body() -> String { REPLY.to_string() }
+
+impl Entry {
+  // a reply to queue; push sets the attempts and when the next one is due
+  pub fn new(id: &str, kind: Kind, rule: &str, body: &str) -> Entry {
+    Entry{
+      id: id.to_string(),
+      kind,
+      thread: String::new(),
+      flair: None,
+      author: String::new(),
+      rule: rule.to_string(),
+      body: body.to_string(),
+      attempts: 0,
+      last_error: String::new(),
+      next_attempt: 0,
+    }
+  }
+}
+
+// what a drain did: the replies it sent, the entries it gave up on without sending, and the
+// ones reddit won't ever take
+#[derive(Default, Debug)]
+pub struct Drained {
+  pub sent: Vec<Entry>,
+  pub gave_up: Vec<Entry>,
+  pub rejected: Vec<Entry>,
+}
+
+pub struct Outbox {
+  pub entries: Vec<Entry>,
+  // retries that failed since the outbox was loaded, and of those the ones given up on
+  pub failed: u32,
+  pub gave_up: u32,
+  max_attempts: u32,
+  backoff_secs: u64,
+}
+
+pub fn now() -> u64 {
+  SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+impl Outbox {
+  pub fn new(max_attempts: u32, backoff_secs: u64) -> Outbox {
+    Outbox{ entries: Vec::new(), failed: 0, gave_up: 0, max_attempts, backoff_secs }
+  }
+
+  pub fn load<S: Store>(store: &S, name: &str, max_attempts: u32, backoff_secs: u64) -> Result<Outbox, Error> {
+    let mut outbox = Outbox::new(max_attempts, backoff_secs);
+    if let Some(contents) = store.read(name) {
+      outbox.entries = serde_json::from_str(&contents)?;
+    }
+    Ok(outbox)
+  }
+
+  pub fn save<S: Store>(&self, store: &mut S, name: &str) -> Result<(), Error> {
+    store.write(name, &serde_json::to_string_pretty(&self.entries)?)
+  }
+
+  pub fn contains(&self, id: &str) -> bool {
+    self.entries.iter().any(|entry| entry.id == id)
+  }
+
+  // exponential backoff: backoff_secs, 2 * backoff_secs, 4 * backoff_secs...
+  fn delay(&self, attempts: u32) -> u64 {
+    let exponent = cmp::min(attempts.saturating_sub(1), 32);
+    self.backoff_secs.saturating_mul(1u64 << exponent)
+  }
+
+  // queue a failed reply, or record another failed attempt if it's already queued
+  pub fn push(&mut self, mut entry: Entry, error: &Error, now: u64) {
+    if let Some(index) = self.entries.iter().position(|queued| queued.id == entry.id) {
+      let mut entry = self.entries.remove(index);
+      entry.last_error = error.to_string();
+      self.retry(entry, now);
+      return;
+    }
+    entry.attempts = 1;
+    entry.last_error = error.to_string();
+    entry.next_attempt = now + self.delay(1);
+    self.entries.push(entry);
+  }
+
+  fn retry(&mut self, mut entry: Entry, now: u64) {
+    entry.attempts += 1;
+    entry.next_attempt = now + self.delay(entry.attempts);
+    self.entries.push(entry);
+  }
+
+  // retry every reply that's due, outside any blackout and within the reply limits, recording
+  // the ones sent in history; the entries sent, given up on or rejected leave the outbox
+  pub fn drain<T: RedditApp>(&mut self, app: &T, history: &mut History, limits: &Limits, blackout: &Blackout, sub: &str, now: u64) -> Drained {
+    let mut drained = Drained::default();
+    let entries = std::mem::take(&mut self.entries);
+    for mut entry in entries {
+      // with the breaker open the call would only fail, so it doesn't count as an attempt
+      if entry.next_attempt > now || app.circuit() == Circuit::Open {
+        self.entries.push(entry);
+        continue;
+      }
+      let _entry = match entry.kind {
+        Kind::Post => logging::context("post", &entry.id),
+        Kind::Comment => logging::context("comment", &entry.id),
+      };
+      let _rule = logging::context("rule", &entry.rule);
+      // a quiet window may have opened, or the thread been blacked out, since it was queued; it
+      // waits without using an attempt, as it does for the limits
+      if let Some(reason) = blackout.reason(&entry.thread, entry.flair.as_deref(), now) {
+        info!("Holding the retry to {}: {}", entry.id, reason);
+        self.entries.push(entry);
+        continue;
+      }
+      if let Some((limit, value)) = history.limited(limits, &entry.thread, &entry.author, now) {
+        info!("Holding the retry to {}: {} ({}) reached", entry.id, limit, value);
+        self.entries.push(entry);
+        continue;
+      }
+      info!("Retrying reply to {} (attempt {})", entry.id, entry.attempts + 1);
+      match app.reply(&entry.id, &entry.rule, &entry.body) {
+        Ok(comment) => {
+          history.record(Reply{
+            id: entry.id.to_string(),
+            thread: entry.thread.to_string(),
+            author: entry.author.to_string(),
+            sub: sub.to_string(),
+            rule: entry.rule.to_string(),
+            at: now,
+            comment,
+          });
+          metrics::inc("rrbot_replies_total", &[("rule", &entry.rule)]);
+          drained.sent.push(entry);
+        },
+        Err(e) if reddit::permanent(&e) => {
+          metrics::inc("rrbot_reply_errors_total", &[("rule", &entry.rule)]);
+          warn!("Reddit won't take the reply to {}: {}", entry.id, e);
+          entry.last_error = e.to_string();
+          drained.rejected.push(entry);
+        },
+        Err(e) => {
+          metrics::inc("rrbot_reply_errors_total", &[("rule", &entry.rule)]);
+          warn!("Error replying to {}: {}", entry.id, e);
+          entry.last_error = e.to_string();
+          self.failed += 1;
+          if entry.attempts + 1 >= self.max_attempts {
+            self.gave_up += 1;
+            error!("Giving up on {} after {} attempts", entry.id, entry.attempts + 1);
+            entry.attempts += 1;
+            drained.gave_up.push(entry);
+          } else {
+            self.retry(entry, now);
+          }
+        }
+      }
+    }
+    drained
+  }
+}
@@ -1,52 +1,80 @@
 use crate::reddit::RedditApp;
+use failure::Error;
 use fancy_regex::Regex;
 use orca::data::Comment;
 use serde::Deserialize;
 use serde_json::Result;
 
 #[derive(Deserialize)]
+#[serde(bound = "")]
 pub struct Post<'a, T: RedditApp>
 where T: RedditApp,
 {
   pub id: String,
-  pub title: String,
   pub selftext: String,
+  #[serde(default)]
+  pub author: String,
+  #[serde(default)]
+  pub link_flair_text: Option<String>,
   #[serde(skip_deserializing)]
   reddit: Option<&'a T>,
 }
 
+pub const RULE: &str = "rr";
+
+// a question the bot answers: the text that asks it and the reply to make
+#[derive(Clone)]
+pub struct Rule {
+  pub name: String,
+  pub pattern: &'static Regex,
+  pub reply: String,
+}
+
+impl Rule {
+  pub fn is_match(&self, text: &str) -> bool {
+    self.pattern.is_match(text).unwrap_or(false)
+  }
+}
+
+// comments with the rule each matches
+pub type Matches<'r> = Vec<(Comment, &'r Rule)>;
+
 lazy_static! {
+  pub static ref REPLY: String = {
+    "The RR is the [Recommended Routine](https://www.reddit.com/r/bodyweightfitness/wiki/kb/recommended_routine).\n*****\n^(I am a bot, flex-beep-boop)".to_string()
+  };
   pub static ref RE: Regex = {
     Regex::new(r#"^(?i)rr\?$|^(w[h]?at|wtf)([`']s|\u{2019}s| is| does)? (a[n]? rr|the rr|rr)( mean| stand for| and where do i find it)?[\?\.]?$|(?<!")(?<! is |did )(w[h]?at|wtf)('s|\u{2019}s| is| does)? (a[n]? rr|the rr|rr)( mean| stand for| and where do i find it)?(?!outine| \w)[\?\.]?(?!")|define rr[\?\.]?"#).unwrap()
   };
-}
-
-fn is_text_match(text: &str) -> Result<bool> {
-  if RE.is_match(&text).unwrap_or(false) {
-    return Ok(true)
-  }
-  Ok(false)
+  pub static ref RULES: Vec<Rule> = vec![
+    Rule{ name: RULE.to_string(), pattern: &RE, reply: REPLY.to_string() },
+  ];
 }
 
 impl<'a, T> Post<'a, T>
-where T: RedditApp + std::default::Default
+where T: RedditApp
 {
   pub fn new(json: &str, reddit: &'a T) -> Result<Post<'a, T>> {
-    let mut post: Post<T> = serde_json::from_str(&json).expect("Error parsing json");
+    let mut post: Post<T> = serde_json::from_str(json).expect("Error parsing json");
     post.reddit = Option::from(reddit);
 
     Ok(post)
   }
-  pub fn comments(&self) -> impl Iterator<Item=Comment> {
-    self.reddit.unwrap().get_comment_tree(&self.id).unwrap_or_default()
+  pub fn comments(&self) -> std::result::Result<impl Iterator<Item=Comment>, Error> {
+    self.reddit.unwrap().get_comment_tree(&self.id)
   }
-  pub fn is_match(&self) -> Result<bool> {
-    is_text_match(&self.selftext)
+  // the first of the rules that matches the post's text
+  pub fn matching_rule<'r>(&self, rules: &'r [Rule]) -> Option<&'r Rule> {
+    rules.iter().find(|rule| rule.is_match(&self.selftext))
   }
-  pub fn get_matching_comments(&self) -> impl Iterator<Item=String> {
-    self.comments()
-      .into_iter()
-      .filter(|comment| is_text_match(&comment.body).unwrap_or(false))
-      .map(|comment| comment.id)
+  // the number of comments read, and each that matches one of the rules with the first rule
+  // it matches; an error if the comments couldn't be read
+  pub fn scan_comments<'r>(&self, rules: &'r [Rule]) -> std::result::Result<(usize, Matches<'r>), Error> {
+    let mut count = 0;
+    let matches = self.comments()?
+      .inspect(|_| count += 1)
+      .filter_map(|comment| rules.iter().find(|rule| rule.is_match(&comment.body)).map(|rule| (comment, rule)))
+      .collect();
+    Ok((count, matches))
   }
 }
\ No newline at end of file
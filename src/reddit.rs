@@ -1,54 +1,439 @@
-
+use base64::Engine;
 use failure::Error;
 use orca::data::Comment;
 use orca::data::Listing;
 use crate::config::Config;
-use orca::{App, Sort};
-use std::fs;
+use crate::health;
+use crate::metrics;
+use log::debug;
+use log::info;
+use log::warn;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::Value;
+use std::cell::RefCell;
+use std::cmp;
+use std::fmt;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+use ureq::Agent;
+use ureq::AgentBuilder;
 
 lazy_static! {
-  static ref REPLY: String = {
-    "The RR is the [Recommended Routine](https://www.reddit.com/r/bodyweightfitness/wiki/kb/recommended_routine).\n*****\n^(I am a bot, flex-beep-boop)".to_string()
-  };
   static ref GOOD_BOT: String = {
     "good bot".to_string()
   };
 }
 
+// the most posts reddit returns in one listing page
+const PAGE_LIMIT: usize = 100;
+
 pub struct Reddit {
-  reddit: App,
-  pub config: Config,
+  agent: Agent,
+  api_url: String,
+  credentials: Credentials,
+  token: RefCell<Token>,
+  max_pages: u32,
+  page_delay: Duration,
 }
 
+// what's needed to authorize again once the token runs out
+struct Credentials {
+  auth_url: String,
+  client_id: String,
+  client_secret: String,
+  username: String,
+  password: String,
+}
+
+struct Token {
+  access_token: String,
+  expires_at: Instant,
+}
+
+// tokens are renewed this long before they expire, so a request never goes out with one
+// that runs out in flight
+const TOKEN_MARGIN: Duration = Duration::from_secs(60);
+// reddit's token lifetime, for responses that don't say
+const DEFAULT_EXPIRES_IN: u64 = 3600;
+
 impl Reddit {
-  pub fn new() -> Reddit {
-    let contents = fs::read_to_string("config.json").expect("Error reading config file");
-    let config = Config::new(&contents).unwrap();
-    let mut reddit = App::new("Linux:com.jasonmichaeladams.rrbot", "0.2", "u/spaceyjase").unwrap();
-    reddit.authorize_script(&config.client_id, &config.client_secret, &config.username, &config.password).unwrap();
-    Reddit{ reddit, config }
+  pub fn new(config: &Config) -> Result<Reddit, Error> {
+    // without a timeout a stalled connection would hang the run, out of the breaker's sight
+    // and deaf to a daemon's stop signal
+    let timeout = Duration::from_secs(config.request_timeout_secs);
+    let agent = AgentBuilder::new().user_agent(&config.user_agent()).timeout_connect(timeout).timeout(timeout).build();
+    let credentials = Credentials{
+      auth_url: config.auth_url.trim_end_matches('/').to_string(),
+      client_id: config.client_id.to_string(),
+      client_secret: config.client_secret.to_string(),
+      username: config.username.to_string(),
+      password: config.password.to_string(),
+    };
+    let token = authorize(&agent, &credentials)?;
+    Ok(Reddit{
+      agent,
+      api_url: config.api_url.trim_end_matches('/').to_string(),
+      credentials,
+      token: RefCell::new(token),
+      max_pages: config.max_pages,
+      page_delay: Duration::from_millis(config.page_delay_ms),
+    })
+  }
+
+  // the current token, renewed first if it's about to expire
+  fn access_token(&self) -> Result<String, Error> {
+    if Instant::now() + TOKEN_MARGIN >= self.token.borrow().expires_at {
+      return self.reauthorize();
+    }
+    Ok(self.token.borrow().access_token.to_string())
+  }
+
+  fn reauthorize(&self) -> Result<String, Error> {
+    let token = authorize(&self.agent, &self.credentials)?;
+    let access_token = token.access_token.to_string();
+    *self.token.borrow_mut() = token;
+    Ok(access_token)
+  }
+
+  // sends a request with the bearer token; a 401 means the token was revoked or expired
+  // early, so it is renewed and the request sent once more
+  fn send<F>(&self, endpoint: &str, request: F) -> Result<Value, Error>
+  where F: Fn(&str) -> Result<ureq::Response, Error>
+  {
+    let request = |authorization: &str| {
+      let start = Instant::now();
+      let response = request(authorization);
+      metrics::api_request(endpoint, &response, ureq::Response::status, start);
+      response
+    };
+    let response = match request(&format!("bearer {}", self.access_token()?)) {
+      Err(e) if matches!(e.downcast_ref(), Some(ureq::Error::Status(401, _))) => request(&format!("bearer {}", self.reauthorize()?))?,
+      response => response?,
+    };
+    Ok(response.into_json()?)
+  }
+
+  // endpoint names the request in the metrics, without ids or sub names in it
+  fn get(&self, endpoint: &str, path: &str, query: &[(&str, &str)]) -> Result<Value, Error> {
+    let url = format!("{}{}", self.api_url, path);
+    debug!("GET {} {:?}", url, query);
+    self.send(endpoint, |authorization| {
+      let request = self.agent.get(&url).set("Authorization", authorization);
+      Ok(query.iter().fold(request, |request, (key, value)| request.query(key, value)).call()?)
+    })
+  }
+
+  fn post(&self, endpoint: &str, path: &str, form: &[(&str, &str)]) -> Result<Value, Error> {
+    let url = format!("{}{}", self.api_url, path);
+    debug!("POST {}", url);
+    self.send(endpoint, |authorization| Ok(self.agent.post(&url).set("Authorization", authorization).send_form(form)?))
+  }
+}
+
+// reddit turned the bot's credentials away
+#[derive(Debug)]
+pub struct AuthFailed(pub String);
+
+impl fmt::Display for AuthFailed {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "Error authorizing: {}", self.0)
+  }
+}
+
+impl std::error::Error for AuthFailed {}
+
+// reddit turned a reply down (THREAD_LOCKED, RATELIMIT, DELETED_COMMENT...); code is the first
+// of the errors it gave
+#[derive(Debug)]
+pub struct Rejected {
+  pub code: String,
+  pub errors: String,
+}
+
+impl fmt::Display for Rejected {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "Error commenting: {}", self.errors)
+  }
+}
+
+impl std::error::Error for Rejected {}
+
+// reddit answered but turned the request down, so it's up: a reply it rejected or a 4xx
+pub fn refused(e: &Error) -> bool {
+  match e.downcast_ref::<ureq::Error>() {
+    Some(ureq::Error::Status(status, _)) => *status < 500,
+    _ => e.downcast_ref::<Rejected>().is_some(),
+  }
+}
+
+// a reply reddit won't ever take, however often it's tried: the thread is locked or archived,
+// the comment deleted, or the bot can't post there. Its rate limit passes, so that isn't
+pub fn permanent(e: &Error) -> bool {
+  match (e.downcast_ref::<Rejected>(), e.downcast_ref::<ureq::Error>()) {
+    (Some(rejected), _) => rejected.code != "RATELIMIT",
+    (_, Some(ureq::Error::Status(status, _))) => *status == 403 || *status == 404,
+    _ => false,
+  }
+}
+
+// reddit couldn't be reached at all, or nothing could be read from it
+#[derive(Debug)]
+pub struct Unavailable(pub String);
+
+impl fmt::Display for Unavailable {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "Reddit is unavailable: {}", self.0)
+  }
+}
+
+impl std::error::Error for Unavailable {}
+
+// script apps use the password grant; the token comes from the auth host, not the api host
+fn authorize(agent: &Agent, credentials: &Credentials) -> Result<Token, Error> {
+  let token = request_token(agent, credentials);
+  health::update(|health| health.authenticated = Some(token.as_ref().map(|_| ()).map_err(|e| e.to_string())));
+  token
+}
+
+fn request_token(agent: &Agent, credentials: &Credentials) -> Result<Token, Error> {
+  let basic = base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", credentials.client_id, credentials.client_secret));
+  let start = Instant::now();
+  let response = agent.post(&format!("{}/api/v1/access_token", credentials.auth_url))
+    .set("Authorization", &format!("Basic {}", basic))
+    .send_form(&[
+      ("grant_type", "password"),
+      ("username", &credentials.username),
+      ("password", &credentials.password),
+    ])
+    .map_err(Error::from);
+  metrics::api_request("access_token", &response, ureq::Response::status, start);
+  let response: Value = match response {
+    Ok(response) => response.into_json()?,
+    // a bad client id or secret is turned away with a 401; a bad password gets a 200 with an
+    // error in place of the token, below
+    Err(e) => return Err(match e.downcast_ref::<ureq::Error>() {
+      Some(ureq::Error::Status(400 | 401 | 403, _)) => AuthFailed(e.to_string()).into(),
+      _ => Unavailable(format!("Error requesting a token: {}", e)).into(),
+    }),
+  };
+  match response["access_token"].as_str() {
+    Some(token) => {
+      let expires_in = response["expires_in"].as_u64().unwrap_or(DEFAULT_EXPIRES_IN);
+      debug!("Authorized as u/{}; the token expires in {}s", credentials.username, expires_in);
+      Ok(Token{ access_token: token.to_string(), expires_at: Instant::now() + Duration::from_secs(expires_in) })
+    },
+    None => Err(AuthFailed(response.to_string()).into()),
+  }
+}
+
+
+// the listing to read a sub's posts from; top covers a time window
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Sort {
+  Hot,
+  New,
+  Rising,
+  Top(Window),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Window {
+  Hour,
+  Day,
+  Week,
+}
+
+// the names sorts are given in config
+pub const SORTS: &[&str] = &["hot", "new", "rising", "top:hour", "top:day", "top:week"];
+
+impl Sort {
+  pub fn parse(name: &str) -> Option<Sort> {
+    match name {
+      "hot" => Some(Sort::Hot),
+      "new" => Some(Sort::New),
+      "rising" => Some(Sort::Rising),
+      "top:hour" => Some(Sort::Top(Window::Hour)),
+      "top:day" => Some(Sort::Top(Window::Day)),
+      "top:week" => Some(Sort::Top(Window::Week)),
+      _ => None,
+    }
+  }
+
+  // the listing path under /r/<sub>/; top's window goes in the query
+  fn path(self) -> String {
+    match self {
+      Sort::Hot => "hot".to_string(),
+      Sort::New => "new".to_string(),
+      Sort::Rising => "rising".to_string(),
+      Sort::Top(_) => "top".to_string(),
+    }
+  }
+}
+
+impl fmt::Display for Window {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      Window::Hour => "hour",
+      Window::Day => "day",
+      Window::Week => "week",
+    })
+  }
+}
+
+impl fmt::Display for Sort {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Sort::Top(window) => write!(f, "top:{}", window),
+      sort => f.write_str(&sort.path()),
+    }
+  }
+}
+
+fn parse_comment(data: &Value) -> Comment {
+  let text = |key: &str| data[key].as_str().unwrap_or_default().to_string();
+  Comment{
+    edited: data["edited"].as_f64(),
+    id: text("id"),
+    body: text("body"),
+    author: text("author"),
+    downs: data["downs"].as_i64().unwrap_or(0),
+    is_submitter: data["is_submitter"].as_bool().unwrap_or(false),
+    link_id: text("link_id"),
+    name: text("name"),
+    parent_id: text("parent_id"),
+    replies: parse_comments(&data["replies"]),
+    score: data["score"].as_i64().unwrap_or(0),
+    score_hidden: data["score_hidden"].as_bool().unwrap_or(false),
+    stickied: data["stickied"].as_bool().unwrap_or(false),
+    subreddit: text("subreddit"),
+    ups: data["ups"].as_i64().unwrap_or(0),
+  }
+}
+
+// reddit sends "" rather than an empty listing when a comment has no replies, and "more"
+// stubs for collapsed threads; both are skipped
+pub fn parse_comments(listing: &Value) -> Listing<Comment> {
+  let mut comments = Listing::<Comment>::new();
+  if let Some(children) = listing["data"]["children"].as_array() {
+    children.iter()
+      .filter(|child| child["kind"] == "t1")
+      .for_each(|child| comments.children.push_back(parse_comment(&child["data"])));
+  }
+  comments
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Circuit {
+  #[default]
+  Closed,
+  Open,
+  // the first call decides: success closes the circuit, failure opens it again
+  HalfOpen,
+}
+
+impl fmt::Display for Circuit {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Circuit::Closed => write!(f, "closed"),
+      Circuit::Open => write!(f, "open"),
+      Circuit::HalfOpen => write!(f, "half-open"),
+    }
   }
 }
 
 pub trait RedditApp {
   fn get_comment_tree(&self, post_id: &str) -> Result<Listing<Comment>, Error>;
-  fn get_posts(&self) -> std::vec::Vec<serde_json::Value>;
-  fn reply(&self, id: &str) -> Result<(), Error>;
+  // up to depth posts from the start of a sub's listing
+  fn get_posts(&self, sub: &str, sort: Sort, depth: usize) -> Result<Vec<Value>, Error>;
+  // replies to a post or comment, returning the new comment's id where there is one
+  fn reply(&self, id: &str, rule: &str, body: &str) -> Result<Option<String>, Error>;
+  // the circuit breaker's state; only a Breaker has one, so anything else is always closed
+  fn circuit(&self) -> Circuit { Circuit::Closed }
+  // called as each run starts
+  fn begin_run(&self) {}
 }
 
 impl RedditApp for Reddit {
   fn get_comment_tree(self: &Reddit, post_id: &str) -> Result<Listing<Comment>, Error> {
-    self.reddit.get_comment_tree(post_id)
+    // the response is [post listing, comment listing]
+    let tree = self.get("comments", &format!("/comments/{}", post_id), &[])?;
+    Ok(parse_comments(&tree[1]))
+  }
+  // follows the listing's after cursor a page at a time until depth posts, the end of the
+  // listing or max_pages; a failed first page is an error, a later one ends the scan with the
+  // posts read so far unless the credentials were turned away
+  fn get_posts(&self, sub: &str, sort: Sort, depth: usize) -> Result<Vec<Value>, Error> {
+    let path = format!("/r/{}/{}", sub, sort.path());
+    let window = match sort {
+      Sort::Top(window) => Some(window.to_string()),
+      _ => None,
+    };
+    let mut posts = Vec::new();
+    let mut after: Option<String> = None;
+    for page in 0..self.max_pages {
+      if page > 0 {
+        thread::sleep(self.page_delay);
+      }
+      let limit = cmp::min(depth - posts.len(), PAGE_LIMIT).to_string();
+      let mut query = vec![("limit", limit.as_str())];
+      if let Some(window) = &window {
+        query.push(("t", window.as_str()));
+      }
+      if let Some(after) = &after {
+        query.push(("after", after.as_str()));
+      }
+      let listing = match self.get("listing", &path, &query) {
+        Ok(listing) => listing,
+        Err(e) if page == 0 || e.downcast_ref::<AuthFailed>().is_some() => return Err(e),
+        Err(e) => {
+          warn!("Error reading page {} of r/{} ({}): {}", page + 1, sub, sort, e);
+          break;
+        }
+      };
+      posts.extend(listing["data"]["children"].as_array().cloned().unwrap_or_default());
+      after = listing["data"]["after"].as_str().map(|after| after.to_string());
+      if posts.len() >= depth || after.is_none() {
+        break;
+      }
+    }
+    posts.truncate(depth);
+    Ok(posts)
   }
-  fn get_posts(&self) -> std::vec::Vec<serde_json::Value> {
-    let posts = self.reddit.get_posts(&self.config.sub, Sort::Hot).unwrap();
-    posts["data"]["children"].as_array().unwrap().to_vec()
+  fn reply(&self, id: &str, _rule: &str, body: &str) -> Result<Option<String>, Error> {
+    let response = self.post("comment", "/api/comment", &[("api_type", "json"), ("thing_id", id), ("text", body)])?;
+    match response["json"]["errors"].as_array() {
+      Some(errors) if !errors.is_empty() => {
+        let code = errors[0][0].as_str().unwrap_or_default().to_string();
+        Err(Rejected{ code, errors: response["json"]["errors"].to_string() }.into())
+      },
+      _ => Ok(response["json"]["data"]["things"][0]["data"]["id"].as_str().map(|id| id.to_string())),
+    }
   }
-  fn reply(&self, id: &str) -> Result<(), Error> {
-    self.reddit.comment(&REPLY, id)
+}
+
+// reads go to the wrapped app but replies are only logged
+pub struct DryRun<T: RedditApp> {
+  pub inner: T,
+}
+
+impl<T: RedditApp> DryRun<T> {
+  pub fn new(inner: T) -> DryRun<T> {
+    DryRun{ inner }
   }
 }
 
-impl Default for Reddit {
-  fn default() -> Self { Option::<Self>::None.unwrap() }
+impl<T: RedditApp> RedditApp for DryRun<T> {
+  fn get_comment_tree(&self, post_id: &str) -> Result<Listing<Comment>, Error> {
+    self.inner.get_comment_tree(post_id)
+  }
+  fn get_posts(&self, sub: &str, sort: Sort, depth: usize) -> Result<Vec<Value>, Error> {
+    self.inner.get_posts(sub, sort, depth)
+  }
+  fn reply(&self, id: &str, rule: &str, body: &str) -> Result<Option<String>, Error> {
+    info!("[dry-run] Would reply to {} (rule: {}):\n{}", id, rule, body);
+    Ok(None)
+  }
 }
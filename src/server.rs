@@ -0,0 +1,68 @@
+use crate::health::Health;
+use crate::metrics;
+use crate::outbox;
+use failure::Error;
+use failure::format_err;
+use log::info;
+use log::warn;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+// how long a client gets to send its request or read the response before it's dropped
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+// serves GET /metrics and GET /healthz (from health) on 127.0.0.1:port from a background
+// thread, for as long as the process runs. Each connection gets a thread of its own, so a
+// client that connects and sends nothing can't hold up a health check
+pub fn serve(port: u16, health: Arc<Mutex<Health>>) -> Result<u16, Error> {
+  let listener = TcpListener::bind(("127.0.0.1", port)).map_err(|e| format_err!("Error listening on port {}: {}", port, e))?;
+  let port = listener.local_addr()?.port();
+  info!("Serving http://127.0.0.1:{}/metrics and /healthz", port);
+  thread::spawn(move || {
+    for stream in listener.incoming().flatten() {
+      let health = Arc::clone(&health);
+      thread::spawn(move || {
+        if let Err(e) = respond(stream, &health) {
+          warn!("Error serving a request: {}", e);
+        }
+      });
+    }
+  });
+  Ok(port)
+}
+
+fn respond(mut stream: TcpStream, health: &Mutex<Health>) -> Result<(), Error> {
+  stream.set_read_timeout(Some(TIMEOUT))?;
+  stream.set_write_timeout(Some(TIMEOUT))?;
+  let mut reader = BufReader::new(stream.try_clone()?);
+  let mut request = String::new();
+  reader.read_line(&mut request)?;
+  // the headers aren't needed, but are read so the client sees a clean close
+  let mut header = String::new();
+  while reader.read_line(&mut header)? > 2 {
+    header.clear();
+  }
+  let mut parts = request.split_whitespace();
+  let (status, content_type, body) = match (parts.next(), parts.next()) {
+    (Some("GET"), Some("/metrics")) => ("200 OK", "text/plain; version=0.0.4", metrics::render()),
+    (Some("GET"), Some("/healthz")) => {
+      let (healthy, report) = health.lock().unwrap().report(outbox::now());
+      let status = if healthy { "200 OK" } else { "503 Service Unavailable" };
+      (status, "application/json", format!("{}\n", report))
+    },
+    _ => ("404 Not Found", "text/plain", "not found\n".to_string()),
+  };
+  write!(
+    stream,
+    "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+    status, content_type, body.len(), body
+  )?;
+  Ok(())
+}
@@ -0,0 +1,55 @@
+use failure::Error;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fs;
+
+// where the bot keeps its state between runs; names are the filenames from the config
+pub trait Store {
+  fn read(&self, name: &str) -> Option<String>;
+  fn write(&mut self, name: &str, contents: &str) -> Result<(), Error>;
+}
+
+#[derive(Default)]
+pub struct FileStore;
+
+impl Store for FileStore {
+  fn read(&self, name: &str) -> Option<String> {
+    fs::read_to_string(name).ok()
+  }
+  fn write(&mut self, name: &str, contents: &str) -> Result<(), Error> {
+    fs::write(name, contents)?;
+    Ok(())
+  }
+}
+
+// keeps the files in memory, so nothing a run writes reaches the disk
+#[derive(Default)]
+pub struct MemoryStore {
+  pub files: HashMap<String, String>,
+}
+
+impl Store for MemoryStore {
+  fn read(&self, name: &str) -> Option<String> {
+    self.files.get(name).cloned()
+  }
+  fn write(&mut self, name: &str, contents: &str) -> Result<(), Error> {
+    self.files.insert(name.to_string(), contents.to_string());
+    Ok(())
+  }
+}
+
+// a db is a set of ids, one per line
+pub fn get_db<S: Store>(store: &S, name: &str) -> HashSet<String> {
+  match store.read(name) {
+    Some(contents) => contents
+    .lines()
+    .map(|x| x.to_string())
+    .collect::<HashSet<_>>(),
+    None => HashSet::new(),
+  }
+}
+
+pub fn write_db<S: Store>(store: &mut S, name: &str, db: &HashSet<String>) -> Result<(), Error> {
+  let contents = db.iter().map(|x| format!("{}\n", x)).collect::<String>();
+  store.write(name, &contents)
+}
@@ -0,0 +1,74 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Instant;
+
+// what a run did, for the log and summary_filename
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub struct Summary {
+  pub subs: u32,
+  // listings read without an error, empty or not
+  pub listings_read: u32,
+  pub posts_scanned: u32,
+  pub comments_scanned: u32,
+  // rule -> posts and comments matching it, answered or not
+  pub matches: BTreeMap<String, u32>,
+  // new replies and retries from the outbox
+  pub replies_sent: u32,
+  pub retries_sent: u32,
+  // left in the outboxes for a later run
+  pub queued: u32,
+  // reason -> matches not answered: already_replied, queued, blackout, thread_dedup or the
+  // max_replies_* limit reached
+  pub skipped: BTreeMap<String, u32>,
+  // kind -> failures: listing, comments, reply, retry, gave_up, rejected
+  pub errors: BTreeMap<String, u32>,
+  // phase -> seconds: outbox, listings, threads, save
+  pub phases: BTreeMap<String, f64>,
+  pub elapsed_secs: f64,
+}
+
+impl Summary {
+  pub fn skip(&mut self, reason: &str) {
+    *self.skipped.entry(reason.to_string()).or_default() += 1;
+  }
+
+  pub fn error(&mut self, kind: &str) {
+    *self.errors.entry(kind.to_string()).or_default() += 1;
+  }
+
+  pub fn matched(&mut self, rule: &str) {
+    *self.matches.entry(rule.to_string()).or_default() += 1;
+  }
+
+  // there were listings to read and none of them could be
+  pub fn nothing_read(&self) -> bool {
+    self.listings_read == 0 && self.errors.contains_key("listing")
+  }
+
+  // adds the time since start to phase
+  pub fn time(&mut self, phase: &str, start: Instant) {
+    *self.phases.entry(phase.to_string()).or_default() += start.elapsed().as_secs_f64();
+  }
+}
+
+impl fmt::Display for Summary {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let total = |counts: &BTreeMap<String, u32>| counts.values().sum::<u32>();
+    write!(f, "scanned {} posts and {} comments in {} subs", self.posts_scanned, self.comments_scanned, self.subs)?;
+    write!(f, "; {} matches{}", total(&self.matches), breakdown(&self.matches, |count| count.to_string()))?;
+    write!(f, "; sent {} replies ({} retried), {} queued", self.replies_sent, self.retries_sent, self.queued)?;
+    write!(f, "; skipped {}{}", total(&self.skipped), breakdown(&self.skipped, |count| count.to_string()))?;
+    write!(f, "; {} errors{}", total(&self.errors), breakdown(&self.errors, |count| count.to_string()))?;
+    write!(f, "; took {:.2}s{}", self.elapsed_secs, breakdown(&self.phases, |secs| format!("{:.2}s", secs)))
+  }
+}
+
+// " (a: 1, b: 2)", or nothing when there's nothing to break down
+fn breakdown<V>(counts: &BTreeMap<String, V>, show: impl Fn(&V) -> String) -> String {
+  if counts.is_empty() {
+    return String::new();
+  }
+  let parts = counts.iter().map(|(name, value)| format!("{}: {}", name, show(value))).collect::<Vec<_>>();
+  format!(" ({})", parts.join(", "))
+}
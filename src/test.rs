@@ -1,35 +1,109 @@
 use crate::config::Config;
+use crate::config::Format;
+use crate::config::LogFormat;
+use crate::config::SubConfig;
+use crate::config::ThreadDedup;
+use crate::daemon;
+use crate::daemon::Signals;
+use crate::lock::Lock;
+use crate::logging;
+use crate::logging::Filter;
+use crate::metrics;
+use crate::metrics::Registry;
+use crate::health::Health;
+use crate::server;
+use crate::lock::Locked;
+use crate::blackout::Blackout;
+use crate::blackout::Review;
+use crate::blackout::Period;
+use crate::throttle::History;
+use crate::throttle::Limits;
+use crate::throttle::Reply;
 use failure::Error;
 use crate::reddit::RedditApp;
+use crate::reddit::Sort;
+use crate::reddit::Window;
+use crate::reddit::DryRun;
+use crate::reddit::Circuit;
 use orca::data::Comment;
 use orca::data::Listing;
 use crate::post::RE;
+use crate::post::RULES;
 use crate::post::Post;
+use crate::outbox::Entry;
+use crate::outbox::Kind;
+use crate::fixture::Fixture;
+use crate::fixture::ReplyRecord;
+use crate::fixture::Replayer;
+use crate::post::REPLY;
+use crate::fake_reddit::FakeReddit;
+use crate::reddit::Reddit;
+use crate::reddit::Rejected;
+use crate::bot::Bot;
+use crate::breaker::Breaker;
+use crate::store::FileStore;
+use crate::store::MemoryStore;
+use crate::store::Store;
+use crate::summary::Summary;
+use crate::validate::Invalid;
+use serde_json::json;
+use std::collections::HashMap;
+use std::fs;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+use std::thread;
+use std::path::Path;
+use std::path::PathBuf;
+use crate::outbox::Outbox;
+use failure::err_msg;
+use std::cell::RefCell;
 
 struct RedditMock
 {
+  // "<sub>/<sort>" -> listing children
+  pub posts: HashMap<String, Vec<serde_json::Value>>,
   pub comments: Listing<Comment>,
+  pub replies: RefCell<Vec<String>>,
+  pub bodies: RefCell<Vec<String>>,
+  pub fail_replies: bool,
+  pub fail_comments: bool,
+  // set after each reply, as a signal arriving mid-run would
+  pub stop_after_reply: Option<Arc<AtomicBool>>,
 }
 
 impl RedditMock {
   pub fn new(comments: Option<Listing<Comment>>) -> RedditMock {
     let comments = comments.unwrap_or_default();
-    RedditMock{ comments }
+    RedditMock{ posts: HashMap::new(), comments, replies: RefCell::new(Vec::new()), bodies: RefCell::new(Vec::new()), fail_replies: false, fail_comments: false, stop_after_reply: None }
   }
 }
 
 impl RedditApp for RedditMock {
   fn get_comment_tree(self: &RedditMock, _post_id: &str) -> Result<Listing<Comment>, Error> {
+    if self.fail_comments {
+      return Err(err_msg("comments failed"));
+    }
     Ok(self.comments.clone())
   }
-  fn get_posts(&self) -> std::vec::Vec<serde_json::Value> {
-    Vec::new()
+  fn get_posts(&self, sub: &str, sort: Sort, depth: usize) -> Result<Vec<serde_json::Value>, Error> {
+    let posts = self.posts.get(&format!("{}/{}", sub, sort)).cloned().unwrap_or_default();
+    Ok(posts.into_iter().take(depth).collect())
+  }
+  fn reply(&self, id: &str, _rule: &str, body: &str) -> std::result::Result<Option<String>, failure::Error> {
+    if self.fail_replies {
+      return Err(err_msg("reply failed"));
+    }
+    self.replies.borrow_mut().push(id.to_string());
+    self.bodies.borrow_mut().push(body.to_string());
+    if let Some(stop) = &self.stop_after_reply {
+      stop.store(true, Ordering::SeqCst);
+    }
+    Ok(Some(format!("r{}", self.replies.borrow().len())))
   }
-  fn reply(&self, _: &str) -> std::result::Result<(), failure::Error> { todo!() }
-}
-
-impl Default for RedditMock {
-  fn default() -> Self { Option::<Self>::None.unwrap() }
 }
 
 #[test]
@@ -48,7 +122,7 @@ fn test_config_parse() {
     }
   "#;
 
-  let config = Config::new(&config).unwrap();
+  let config = Config::new(config).unwrap();
 
   assert_eq!(config.client_id, "client_id");
   assert_eq!(config.client_secret, "client_secret");
@@ -59,6 +133,9 @@ fn test_config_parse() {
   assert_eq!(config.posts_db_filename, "posts.db");
   assert_eq!(config.comments_db_filename, "comments.db");
   assert_eq!(config.sub, "sub");
+  assert_eq!(config.outbox_filename, "outbox.json");
+  assert_eq!(config.outbox_max_attempts, 5);
+  assert_eq!(config.outbox_backoff_secs, 300);
 }
 
 #[test]
@@ -285,7 +362,7 @@ fn test_matching_post_false() {
     }"#;
   let mock = RedditMock::new(Option::None);
   let post = Post::new(json, &mock).unwrap();
-  assert_eq!(post.is_match().unwrap_or(false), false);
+  assert!(post.matching_rule(&RULES).is_none());
 }
 
 #[test]
@@ -398,7 +475,7 @@ fn test_matching_post_true() {
     }"#;
   let mock = RedditMock::new(Option::None);
   let post = Post::new(json, &mock).unwrap();
-  assert_eq!(post.is_match().unwrap_or(false), true);
+  assert!(post.matching_rule(&RULES).is_some());
 }
 
 #[test]
@@ -530,7 +607,7 @@ fn test_comment_match_false() {
   });
   let mock = RedditMock::new(Option::from(listing));
   let post = Post::new(json, &mock).unwrap();
-  let matches = post.get_matching_comments().collect::<Vec<_>>();
+  let (_, matches) = post.scan_comments(&RULES).unwrap();
   assert_eq!(matches.len(), 0);
 }
 
@@ -664,7 +741,7 @@ fn test_comment_match_true() {
   });
   let mock = RedditMock::new(Option::from(listing));
   let post = Post::new(json, &mock).unwrap();
-  let matches = post.get_matching_comments().collect::<Vec<_>>();
+  let (_, matches) = post.scan_comments(&RULES).unwrap();
   assert_eq!(matches.len(), 1);
 }
 
@@ -865,4 +942,1820 @@ fn test_regex_match_unicode_from_test_sub() {
 fn test_regex_match_unicode_from_test_sub_match() {
   let query = r#""Yeah but what's the RR? I should have a reply…""#.to_string();
   assert!(RE.is_match(&query).unwrap());
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_outbox_push_backoff() {
+  let mut outbox = Outbox::new(5, 60);
+  outbox.push(Entry::new("abc", Kind::Comment, "rr", "body"), &err_msg("503"), 1000);
+  assert!(outbox.contains("abc"));
+  assert_eq!(outbox.entries[0].attempts, 1);
+  assert_eq!(outbox.entries[0].next_attempt, 1060);
+  outbox.push(Entry::new("abc", Kind::Comment, "rr", "body"), &err_msg("502"), 2000);
+  assert_eq!(outbox.entries.len(), 1);
+  assert_eq!(outbox.entries[0].attempts, 2);
+  assert_eq!(outbox.entries[0].last_error, "502");
+  assert_eq!(outbox.entries[0].next_attempt, 2120);
+}
+
+#[test]
+fn test_outbox_drain_sends_due() {
+  let mock = RedditMock::new(Option::None);
+  let mut outbox = Outbox::new(5, 60);
+  outbox.push(Entry::new("due", Kind::Post, "rr", "body"), &err_msg("503"), 1000);
+  outbox.push(Entry::new("later", Kind::Comment, "rr", "body"), &err_msg("503"), 1100);
+  let drained = outbox.drain(&mock, &mut History::default(), &Limits::default(), &Blackout::new(&test_config()), "sub", 1060);
+  assert_eq!(drained.sent.len(), 1);
+  assert_eq!(drained.sent[0].id, "due");
+  assert_eq!(drained.sent[0].kind, Kind::Post);
+  assert!(drained.gave_up.is_empty());
+  assert_eq!(*mock.replies.borrow(), vec!["due".to_string()]);
+  assert!(!outbox.contains("due"));
+  assert!(outbox.contains("later"));
+}
+
+#[test]
+fn test_outbox_drain_retries_failed() {
+  let mut mock = RedditMock::new(Option::None);
+  mock.fail_replies = true;
+  let mut outbox = Outbox::new(5, 60);
+  outbox.push(Entry::new("abc", Kind::Comment, "rr", "body"), &err_msg("503"), 1000);
+  let drained = outbox.drain(&mock, &mut History::default(), &Limits::default(), &Blackout::new(&test_config()), "sub", 1060);
+  assert!(drained.sent.is_empty() && drained.gave_up.is_empty());
+  assert_eq!(outbox.entries[0].attempts, 2);
+  assert_eq!(outbox.entries[0].last_error, "reply failed");
+  assert_eq!(outbox.entries[0].next_attempt, 1180);
+}
+
+#[test]
+fn test_outbox_drain_gives_up() {
+  let mut mock = RedditMock::new(Option::None);
+  mock.fail_replies = true;
+  let mut outbox = Outbox::new(2, 60);
+  outbox.push(Entry::new("abc", Kind::Comment, "rr", "body"), &err_msg("503"), 1000);
+  let drained = outbox.drain(&mock, &mut History::default(), &Limits::default(), &Blackout::new(&test_config()), "sub", 1060);
+  assert!(drained.sent.is_empty());
+  assert_eq!(drained.gave_up.len(), 1);
+  assert_eq!(drained.gave_up[0].attempts, 2);
+  assert!(outbox.entries.is_empty());
+}
+
+#[test]
+fn test_outbox_drain_counts_against_limits() {
+  let mock = RedditMock::new(Option::None);
+  let mut outbox = Outbox::new(5, 60);
+  for (id, author) in &[("c1", "a"), ("c2", "b")] {
+    let entry = Entry{ thread: "p1".to_string(), author: author.to_string(), ..Entry::new(id, Kind::Comment, "rr", "body") };
+    outbox.push(entry, &err_msg("503"), 1000);
+  }
+  let mut history = History::default();
+  let limits = Limits{ per_thread: Some(1), ..Limits::default() };
+  let drained = outbox.drain(&mock, &mut history, &limits, &Blackout::new(&test_config()), "sub", 1060);
+  assert_eq!(drained.sent.len(), 1);
+  assert_eq!(*mock.replies.borrow(), vec!["c1".to_string()]);
+  assert_eq!(history.replies, vec![Reply{ comment: Some("r1".to_string()), at: 1060, ..history_reply("c1", "p1", "a", 0) }]);
+  assert_eq!(history.this_run, 1);
+  // held back without using up an attempt
+  assert_eq!(outbox.entries.len(), 1);
+  assert_eq!(outbox.entries[0].id, "c2");
+  assert_eq!(outbox.entries[0].attempts, 1);
+}
+
+#[test]
+fn test_outbox_drain_holds_blackouts() {
+  let mock = RedditMock::new(Option::None);
+  let mut config = test_config();
+  config.quiet_hours = vec!["22:00-06:00".to_string()];
+  config.blackout_posts = vec!["p2".to_string()];
+  config.blackout_flairs = vec!["Meta".to_string()];
+  let blackout = Blackout::new(&config);
+  let mut outbox = Outbox::new(5, 60);
+  outbox.push(Entry{ thread: "p1".to_string(), ..Entry::new("c1", Kind::Comment, "rr", "body") }, &err_msg("503"), 0);
+  outbox.push(Entry{ thread: "p2".to_string(), ..Entry::new("c2", Kind::Comment, "rr", "body") }, &err_msg("503"), 0);
+  outbox.push(Entry{ thread: "p3".to_string(), flair: Some("meta".to_string()), ..Entry::new("c3", Kind::Comment, "rr", "body") }, &err_msg("503"), 0);
+  let mut history = History::default();
+
+  // 23:00 is quiet, so nothing goes out and no attempt is used
+  let drained = outbox.drain(&mock, &mut history, &Limits::default(), &blackout, "sub", 23 * 60 * 60);
+  assert!(drained.sent.is_empty());
+  assert!(outbox.entries.iter().all(|entry| entry.attempts == 1));
+
+  // at noon only the thread that isn't blacked out is answered
+  let drained = outbox.drain(&mock, &mut history, &Limits::default(), &blackout, "sub", 12 * 60 * 60);
+  assert_eq!(drained.sent.iter().map(|entry| entry.id.as_str()).collect::<Vec<_>>(), vec!["c1"]);
+  assert_eq!(*mock.replies.borrow(), vec!["c1".to_string()]);
+  assert!(outbox.contains("c2") && outbox.contains("c3"));
+}
+
+#[test]
+fn test_dry_run_does_not_reply() {
+  let mut listing = Listing::<Comment>::new();
+  listing.children.push_back(Comment{
+    edited: Option::None,
+    id: "cj0z5z".to_string(),
+    body: "what is the rr?".to_string(),
+    author: "".to_string(),
+    downs: 0,
+    is_submitter: false,
+    link_id: "t3_qvxrbp".to_string(),
+    name: "t1_cj0z5z".to_string(),
+    parent_id: "t3_qvxrbp".to_string(),
+    replies: Listing::<Comment>::new(),
+    score: 0,
+    score_hidden: false,
+    stickied: false,
+    subreddit: "".to_string(),
+    ups: 0,
+  });
+  let app = DryRun::new(RedditMock::new(Option::from(listing)));
+  assert_eq!(app.get_comment_tree("qvxrbp").unwrap().children.len(), 1);
+  assert!(app.reply("cj0z5z", "rr", "body").is_ok());
+  assert!(app.inner.replies.borrow().is_empty());
+}
+
+fn test_config() -> Config {
+  let config = r#"
+    {
+      "client_id": "client_id",
+      "client_secret": "client_secret",
+      "username": "username",
+      "password": "password",
+      "hot_take": 50,
+      "inbox_db_filename": "inbox.db",
+      "posts_db_filename": "posts.db",
+      "comments_db_filename": "comments.db",
+      "sub": "sub"
+    }
+  "#;
+  Config::new(config).unwrap()
+}
+
+#[test]
+fn test_replay_makes_recorded_replies() {
+  let mut fixture = Fixture::new(r#"
+    {
+      "posts": {
+        "sub/hot": [
+          { "kind": "t3", "data": { "id": "p1", "title": "RR?", "selftext": "what is the rr?" } },
+          { "kind": "t3", "data": { "id": "p2", "title": "Daily thread", "selftext": "Hello" } }
+        ]
+      },
+      "comment_trees": {
+        "p1": [],
+        "p2": [
+          { "id": "c1", "name": "t1_c1", "body": "rr?", "author": "a", "link_id": "t3_p2", "parent_id": "t3_p2", "subreddit": "sub" },
+          { "id": "c2", "name": "t1_c2", "body": "Hello World", "author": "b", "link_id": "t3_p2", "parent_id": "t3_p2", "subreddit": "sub" }
+        ]
+      }
+    }
+  "#).unwrap();
+  fixture.replies = vec!["p1", "c1"].into_iter()
+    .map(|id| ReplyRecord{ id: id.to_string(), rule: "rr".to_string(), body: REPLY.to_string(), comment: None })
+    .collect();
+
+  let mut bot = Bot::new(Replayer::new(fixture), MemoryStore::default(), test_config());
+  bot.dry_run = true;
+  bot.run().unwrap();
+  assert!(bot.app.matches_recording());
+}
+
+#[test]
+fn test_record_then_replay() {
+  let reddit = FakeReddit::start();
+  reddit.add_post("p1", "RR?", "what is the rr?");
+  reddit.add_post("p2", "Daily thread", "Hello");
+  reddit.add_comment("p2", "c1", "rr?");
+  reddit.add_comment("p2", "c2", "what's the rr?");
+  let dir = temp_dir("record-replay");
+  let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+  let mut config = fake_config_json(&reddit, &dir);
+  config["thread_dedup"] = json!("link");
+  fs::write(dir.join("config.json"), config.to_string()).unwrap();
+  // answered before the recording, so neither run replies to it
+  fs::write(dir.join("posts.db"), "p1\n").unwrap();
+
+  let options = crate::Options{ config: Some(path("config.json")), record: Some(path("fixture.json")), ..Default::default() };
+  crate::run(&options).unwrap();
+  assert_eq!(reddit.replies().len(), 2);
+  let fixture = Fixture::load(&path("fixture.json")).unwrap();
+  assert_eq!(fixture.store[&path("posts.db")], "p1\n");
+
+  // the live files have moved on since; the replay starts from the recorded ones and leaves
+  // these alone
+  let options = crate::Options{ config: Some(path("config.json")), replay: Some(path("fixture.json")), ..Default::default() };
+  let posts = fs::read_to_string(dir.join("posts.db")).unwrap();
+  let comments = fs::read_to_string(dir.join("comments.db")).unwrap();
+  let summary = crate::run(&options).unwrap().unwrap();
+  assert_eq!(summary.replies_sent, 2);
+  assert_eq!(summary.skipped["already_replied"], 1);
+  assert_eq!(fs::read_to_string(dir.join("posts.db")).unwrap(), posts);
+  assert_eq!(fs::read_to_string(dir.join("comments.db")).unwrap(), comments);
+  assert_eq!(reddit.replies().len(), 2);
+
+  // replies that differ from the recording fail the replay
+  let mut fixture = fixture;
+  fixture.replies.pop();
+  fixture.save(&path("fixture.json")).unwrap();
+  let error = crate::run(&options).unwrap_err();
+  assert_eq!(crate::exit_code(&error), crate::EXIT_ERROR);
+  assert!(error.to_string().starts_with("Replies differ from those recorded in"));
+}
+
+#[test]
+fn test_replay_missing_comment_tree() {
+  let app = Replayer::new(Fixture::default());
+  assert!(app.get_comment_tree("p1").is_err());
+  assert!(app.get_posts("sub", Sort::Hot, 10).unwrap().is_empty());
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+  let dir = std::env::temp_dir().join(format!("rrbot-{}-{}", name, std::process::id()));
+  let _ = fs::remove_dir_all(&dir);
+  fs::create_dir_all(&dir).unwrap();
+  dir
+}
+
+fn fake_config(reddit: &FakeReddit, dir: &Path) -> Config {
+  Config::new(&fake_config_json(reddit, dir).to_string()).unwrap()
+}
+
+// the config for the fake reddit as json, to change or write to a file
+fn fake_config_json(reddit: &FakeReddit, dir: &Path) -> serde_json::Value {
+  let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+  json!({
+    "client_id": "client_id",
+    "client_secret": "client_secret",
+    "username": "username",
+    "password": "password",
+    "hot_take": 50,
+    "inbox_db_filename": path("inbox.db"),
+    "posts_db_filename": path("posts.db"),
+    "comments_db_filename": path("comments.db"),
+    "outbox_filename": path("outbox.json"),
+    "history_filename": path("history.json"),
+    "held_filename": path("held.json"),
+    "breaker_filename": path("breaker.json"),
+    "lock_filename": path("rrbot.lock"),
+    "sub": "sub",
+    "page_delay_ms": 0,
+    "api_url": reddit.url(),
+    "auth_url": reddit.url(),
+  })
+}
+
+#[test]
+fn test_end_to_end_replies() {
+  let reddit = FakeReddit::start();
+  reddit.add_post("p1", "RR?", "what is the rr?");
+  reddit.add_post("p2", "Daily thread", "Hello");
+  reddit.add_comment("p2", "c1", "rr?");
+  reddit.add_comment("p2", "c2", "Hello World");
+  let dir = temp_dir("end-to-end-replies");
+  let config = fake_config(&reddit, &dir);
+
+  let mut bot = Bot::new(Reddit::new(&config).unwrap(), FileStore, config);
+  bot.run().unwrap();
+
+  let replies = reddit.replies();
+  assert_eq!(replies.iter().map(|(id, _)| id.as_str()).collect::<Vec<_>>(), vec!["p1", "c1"]);
+  assert!(replies.iter().all(|(_, text)| *text == *REPLY));
+  assert_eq!(fs::read_to_string(dir.join("posts.db")).unwrap(), "p1\n");
+  assert_eq!(fs::read_to_string(dir.join("comments.db")).unwrap(), "c1\n");
+
+  // already replied to, so a second scan is quiet
+  bot.run().unwrap();
+  assert_eq!(reddit.replies().len(), 2);
+}
+
+#[test]
+fn test_end_to_end_failed_replies_are_queued() {
+  let reddit = FakeReddit::start();
+  reddit.add_post("p1", "Daily thread", "Hello");
+  reddit.add_comment("p1", "c1", "what's the rr?");
+  reddit.state.lock().unwrap().reply_status = Some(503);
+  let dir = temp_dir("end-to-end-failed");
+  let config = fake_config(&reddit, &dir);
+
+  let mut bot = Bot::new(Reddit::new(&config).unwrap(), FileStore, config);
+  bot.run().unwrap();
+
+  assert!(reddit.replies().is_empty());
+  assert_eq!(fs::read_to_string(dir.join("comments.db")).unwrap(), "");
+  let outbox = Outbox::load(&bot.store, &bot.config.outbox_filename, 5, 60).unwrap();
+  assert!(outbox.contains("c1"));
+}
+
+#[test]
+fn test_end_to_end_rejected_replies_are_marked() {
+  let reddit = FakeReddit::start();
+  reddit.add_post("p1", "Daily thread", "Hello");
+  reddit.add_comment("p1", "c1", "what's the rr?");
+  reddit.state.lock().unwrap().reply_error = Some("THREAD_LOCKED".to_string());
+  let dir = temp_dir("end-to-end-rejected");
+  let config = fake_config(&reddit, &dir);
+
+  let mut bot = Bot::new(Reddit::new(&config).unwrap(), FileStore, config);
+  let summary = bot.run().unwrap();
+  assert_eq!(summary.errors["rejected"], 1);
+  assert_eq!(fs::read_to_string(dir.join("comments.db")).unwrap(), "c1\n");
+  let outbox = Outbox::load(&bot.store, &bot.config.outbox_filename, 5, 60).unwrap();
+  assert!(outbox.entries.is_empty());
+
+  // handled, so the next run doesn't try again or fail
+  let summary = bot.run().unwrap();
+  assert!(summary.errors.is_empty());
+  assert_eq!(summary.skipped["already_replied"], 1);
+}
+
+#[test]
+fn test_end_to_end_rate_limited_replies_are_queued() {
+  let reddit = FakeReddit::start();
+  reddit.add_post("p1", "Daily thread", "Hello");
+  reddit.add_comment("p1", "c1", "what's the rr?");
+  reddit.state.lock().unwrap().reply_error = Some("RATELIMIT".to_string());
+  let dir = temp_dir("end-to-end-rate-limited");
+  let config = fake_config(&reddit, &dir);
+
+  let mut bot = Bot::new(Reddit::new(&config).unwrap(), FileStore, config);
+  let summary = bot.run().unwrap();
+  assert_eq!(summary.errors["reply"], 1);
+  assert_eq!(fs::read_to_string(dir.join("comments.db")).unwrap(), "");
+  let outbox = Outbox::load(&bot.store, &bot.config.outbox_filename, 5, 60).unwrap();
+  assert!(outbox.contains("c1"));
+}
+
+#[test]
+fn test_end_to_end_rejected_retries_are_marked() {
+  let reddit = FakeReddit::start();
+  reddit.state.lock().unwrap().reply_error = Some("DELETED_COMMENT".to_string());
+  let dir = temp_dir("end-to-end-rejected-retry");
+  let config = fake_config(&reddit, &dir);
+  let mut bot = Bot::new(Reddit::new(&config).unwrap(), FileStore, config);
+  let mut outbox = Outbox::new(5, 60);
+  outbox.push(Entry::new("c1", Kind::Comment, "rr", "body"), &err_msg("timed out"), 0);
+  outbox.save(&mut bot.store, &bot.config.outbox_filename).unwrap();
+
+  let summary = bot.run().unwrap();
+  assert_eq!(summary.errors["rejected"], 1);
+  assert_eq!(fs::read_to_string(dir.join("comments.db")).unwrap(), "c1\n");
+  let outbox = Outbox::load(&bot.store, &bot.config.outbox_filename, 5, 60).unwrap();
+  assert!(outbox.entries.is_empty());
+}
+
+#[test]
+fn test_end_to_end_request_timeout() {
+  let reddit = FakeReddit::start();
+  let dir = temp_dir("end-to-end-timeout");
+  let mut config = fake_config(&reddit, &dir);
+  // connections are queued by the OS but never answered
+  let silent = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+  config.api_url = format!("http://{}", silent.local_addr().unwrap());
+  config.request_timeout_secs = 1;
+  let app = Reddit::new(&config).unwrap();
+  let start = Instant::now();
+  assert!(app.get_posts("sub", Sort::Hot, 10).is_err());
+  assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn test_end_to_end_pagination() {
+  let reddit = FakeReddit::start();
+  (1..=7).for_each(|n| reddit.add_post(&format!("p{}", n), "title", if n == 6 { "what is the rr?" } else { "Hello" }));
+  reddit.state.lock().unwrap().page_size = Some(2);
+  let dir = temp_dir("end-to-end-pagination");
+  let mut config = fake_config(&reddit, &dir);
+  config.hot_take = 6;
+  let app = Reddit::new(&config).unwrap();
+
+  let posts = app.get_posts("sub", Sort::Top(Window::Week), 5).unwrap();
+  assert_eq!(posts.iter().map(|post| post["data"]["id"].as_str().unwrap()).collect::<Vec<_>>(), vec!["p1", "p2", "p3", "p4", "p5"]);
+  assert_eq!(reddit.state.lock().unwrap().listing_requests, vec![
+    "limit=5&t=week",
+    "limit=3&t=week&after=t3_p2",
+    "limit=1&t=week&after=t3_p4",
+  ]);
+
+  // the scan reaches p6 on the third page
+  let mut bot = Bot::new(app, FileStore, config);
+  bot.run().unwrap();
+  assert_eq!(reddit.replies().iter().map(|(id, _)| id.as_str()).collect::<Vec<_>>(), vec!["p6"]);
+}
+
+#[test]
+fn test_end_to_end_max_pages() {
+  let reddit = FakeReddit::start();
+  (1..=5).for_each(|n| reddit.add_post(&format!("p{}", n), "title", "Hello"));
+  reddit.state.lock().unwrap().page_size = Some(1);
+  let dir = temp_dir("end-to-end-max-pages");
+  let mut config = fake_config(&reddit, &dir);
+  config.max_pages = 2;
+
+  let posts = Reddit::new(&config).unwrap().get_posts("sub", Sort::New, 50).unwrap();
+  assert_eq!(posts.len(), 2);
+  assert_eq!(reddit.state.lock().unwrap().listing_requests.len(), 2);
+}
+
+fn comment(id: &str, body: &str) -> Comment {
+  Comment{
+    edited: Option::None,
+    id: id.to_string(),
+    body: body.to_string(),
+    author: "".to_string(),
+    downs: 0,
+    is_submitter: false,
+    link_id: "t3_p1".to_string(),
+    name: format!("t1_{}", id),
+    parent_id: "t3_p1".to_string(),
+    replies: Listing::<Comment>::new(),
+    score: 0,
+    score_hidden: false,
+    stickied: false,
+    subreddit: "".to_string(),
+    ups: 0,
+  }
+}
+
+fn test_bot(posts: &[(&str, &str)], comments: &[(&str, &str)]) -> Bot<RedditMock, MemoryStore> {
+  let mut listing = Listing::<Comment>::new();
+  comments.iter().for_each(|(id, body)| listing.children.push_back(comment(id, body)));
+  let mut mock = RedditMock::new(Option::from(listing));
+  mock.posts.insert("sub/hot".to_string(), listing_posts(posts));
+  Bot::new(mock, MemoryStore::default(), test_config())
+}
+
+fn listing_posts(posts: &[(&str, &str)]) -> Vec<serde_json::Value> {
+  posts.iter()
+    .map(|(id, selftext)| json!({ "kind": "t3", "data": { "id": id, "title": "title", "selftext": selftext } }))
+    .collect()
+}
+
+#[test]
+fn test_bot_replies_to_matches() {
+  let mut bot = test_bot(&[("p1", "what is the rr?"), ("p2", "Hello")], &[("c1", "rr?"), ("c2", "Hello World")]);
+  bot.run().unwrap();
+  assert_eq!(*bot.app.replies.borrow(), vec!["p1".to_string(), "c1".to_string()]);
+  assert_eq!(bot.store.read("posts.db").unwrap(), "p1\n");
+  assert_eq!(bot.store.read("comments.db").unwrap(), "c1\n");
+}
+
+#[test]
+fn test_bot_counts_unreadable_comments() {
+  let mut bot = test_bot(&[("p1", "what is the rr?")], &[("c1", "rr?")]);
+  bot.app.fail_comments = true;
+  let summary = bot.run().unwrap();
+  assert_eq!(summary.errors["comments"], 1);
+  assert_eq!(summary.comments_scanned, 0);
+  // the post is still answered
+  assert_eq!(*bot.app.replies.borrow(), vec!["p1".to_string()]);
+}
+
+#[test]
+fn test_bot_skips_replied() {
+  let mut bot = test_bot(&[("p1", "what is the rr?")], &[("c1", "rr?")]);
+  bot.store.write("posts.db", "p1\n").unwrap();
+  bot.store.write("comments.db", "c1\n").unwrap();
+  bot.run().unwrap();
+  assert!(bot.app.replies.borrow().is_empty());
+}
+
+#[test]
+fn test_bot_hot_take() {
+  let mut bot = test_bot(&[("p1", "Hello"), ("p2", "what is the rr?")], &[]);
+  bot.config.hot_take = 1;
+  bot.run().unwrap();
+  assert!(bot.app.replies.borrow().is_empty());
+}
+
+#[test]
+fn test_bot_queues_failed_replies() {
+  let mut bot = test_bot(&[("p1", "what is the rr?")], &[("c1", "rr?")]);
+  bot.app.fail_replies = true;
+  bot.run().unwrap();
+  assert_eq!(bot.store.read("posts.db").unwrap(), "");
+  let outbox = Outbox::load(&bot.store, "outbox.json", 5, 60).unwrap();
+  assert!(outbox.contains("p1"));
+  assert!(outbox.contains("c1"));
+
+  // the outbox is drained on the next run, once the retry is due
+  bot.app.fail_replies = false;
+  let mut outbox = outbox;
+  outbox.entries.iter_mut().for_each(|entry| entry.next_attempt = 0);
+  outbox.save(&mut bot.store, "outbox.json").unwrap();
+  bot.run().unwrap();
+  assert_eq!(bot.app.replies.borrow().len(), 2);
+  assert_eq!(bot.store.read("posts.db").unwrap(), "p1\n");
+  assert_eq!(bot.store.read("comments.db").unwrap(), "c1\n");
+}
+
+#[test]
+fn test_bot_does_not_mark_given_up_replies() {
+  let mut bot = test_bot(&[], &[]);
+  bot.app.fail_replies = true;
+  let mut outbox = Outbox::new(bot.config.outbox_max_attempts, 60);
+  outbox.push(Entry::new("c1", Kind::Comment, "rr", "body"), &err_msg("503"), 0);
+  outbox.entries[0].attempts = bot.config.outbox_max_attempts - 1;
+  outbox.save(&mut bot.store, "outbox.json").unwrap();
+  let summary = bot.run().unwrap();
+  assert_eq!(summary.errors["gave_up"], 1);
+  assert_eq!(summary.retries_sent, 0);
+  assert_eq!(bot.store.read("comments.db").unwrap(), "");
+  assert!(Outbox::load(&bot.store, "outbox.json", 5, 60).unwrap().entries.is_empty());
+}
+
+#[test]
+fn test_bot_dry_run_leaves_store() {
+  let mut bot = test_bot(&[("p1", "what is the rr?")], &[("c1", "rr?")]);
+  bot.dry_run = true;
+  bot.run().unwrap();
+  assert!(bot.store.files.is_empty());
+}
+
+#[test]
+fn test_bot_multiple_subs() {
+  let mut bot = test_bot(&[("p1", "what is the rr?")], &[]);
+  bot.app.posts.insert("other/hot".to_string(), listing_posts(&[("p2", "what is the rr?"), ("p3", "rr?")]));
+  bot.config.subs = vec![
+    SubConfig{ name: "sub".to_string(), ..SubConfig::default() },
+    SubConfig{ name: "other".to_string(), hot_take: Some(1), ..SubConfig::default() },
+  ];
+  bot.run().unwrap();
+  assert_eq!(*bot.app.replies.borrow(), vec!["p1".to_string(), "p2".to_string()]);
+  assert_eq!(bot.store.read("posts.sub.db").unwrap(), "p1\n");
+  assert_eq!(bot.store.read("posts.other.db").unwrap(), "p2\n");
+  assert!(bot.store.read("posts.db").is_none());
+}
+
+#[test]
+fn test_bot_sub_without_rules() {
+  let mut bot = test_bot(&[("p1", "what is the rr?")], &[("c1", "rr?")]);
+  bot.config.subs = vec![SubConfig{ name: "sub".to_string(), rules: Some(Vec::new()), ..SubConfig::default() }];
+  bot.run().unwrap();
+  assert!(bot.app.replies.borrow().is_empty());
+}
+
+#[test]
+fn test_bot_combines_sorts() {
+  let mut bot = test_bot(&[("p1", "what is the rr?"), ("p2", "Hello")], &[]);
+  bot.app.posts.insert("sub/new".to_string(), listing_posts(&[("p3", "rr?"), ("p1", "what is the rr?")]));
+  bot.app.posts.insert("sub/top:week".to_string(), listing_posts(&[("p4", "define rr")]));
+  bot.config.sorts = vec!["hot".to_string(), "new".to_string()];
+  bot.run().unwrap();
+  assert_eq!(*bot.app.replies.borrow(), vec!["p1".to_string(), "p3".to_string()]);
+
+  // hot_take applies to each listing
+  let mut bot = test_bot(&[("p1", "Hello"), ("p2", "what is the rr?")], &[]);
+  bot.app.posts.insert("sub/new".to_string(), listing_posts(&[("p3", "Hello"), ("p4", "rr?")]));
+  bot.config.hot_take = 1;
+  bot.config.subs = vec![SubConfig{ name: "sub".to_string(), sorts: Some(vec!["new".to_string(), "top:week".to_string()]), ..SubConfig::default() }];
+  bot.run().unwrap();
+  assert!(bot.app.replies.borrow().is_empty());
+}
+
+const LAYERED_CONFIG: &str = r#"
+  {
+    "client_id": "client_id",
+    "client_secret": "client_secret",
+    "username": "username",
+    "password": "password",
+    "hot_take": 50,
+    "inbox_db_filename": "inbox.db",
+    "posts_db_filename": "posts.db",
+    "comments_db_filename": "comments.db",
+    "sub": "sub"
+  }
+"#;
+
+fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+  pairs.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+}
+
+#[test]
+fn test_config_env_overrides() {
+  let env = pairs(&[("RRBOT_SUB", "env_sub"), ("RRBOT_HOT_TAKE", "25"), ("HOME", "/root"), ("RRBOT_CONFIG", "other.json")]);
+  let config = Config::layered(LAYERED_CONFIG, Format::Json, env, &[]).unwrap();
+  assert_eq!(config.sub, "env_sub");
+  assert_eq!(config.hot_take, 25);
+  assert_eq!(config.username, "username");
+}
+
+#[test]
+fn test_config_flags_override_env() {
+  let env = pairs(&[("RRBOT_SUB", "env_sub"), ("RRBOT_OUTBOX_FILENAME", "env.json")]);
+  let overrides = pairs(&[("sub", "flag_sub"), ("outbox_max_attempts", "9")]);
+  let config = Config::layered(LAYERED_CONFIG, Format::Json, env, &overrides).unwrap();
+  assert_eq!(config.sub, "flag_sub");
+  assert_eq!(config.outbox_filename, "env.json");
+  assert_eq!(config.outbox_max_attempts, 9);
+}
+
+#[test]
+fn test_config_unknown_flag() {
+  let overrides = pairs(&[("subreddit", "sub")]);
+  assert!(Config::layered(LAYERED_CONFIG, Format::Json, Vec::new(), &overrides).is_err());
+}
+
+#[test]
+fn test_config_bad_number() {
+  let overrides = pairs(&[("hot_take", "lots")]);
+  assert!(Config::layered(LAYERED_CONFIG, Format::Json, Vec::new(), &overrides).is_err());
+}
+
+#[test]
+fn test_config_redacted() {
+  let config = Config::layered(LAYERED_CONFIG, Format::Json, Vec::new(), &[]).unwrap();
+  let redacted = config.redacted();
+  assert!(!redacted.contains("client_secret\": \"client_secret"));
+  assert!(!redacted.contains("\"password\": \"password\""));
+  assert!(redacted.contains("\"client_id\": \"client_id\""));
+  assert!(redacted.contains("<redacted>"));
+}
+
+const CONFIG_WITHOUT_SECRETS: &str = r#"
+  {
+    "client_id": "client_id",
+    "username": "username",
+    "hot_take": 50,
+    "inbox_db_filename": "inbox.db",
+    "posts_db_filename": "posts.db",
+    "comments_db_filename": "comments.db",
+    "sub": "sub"
+  }
+"#;
+
+#[cfg(unix)]
+fn write_secrets(dir: &Path, mode: u32) -> String {
+  use std::os::unix::fs::PermissionsExt;
+  let path = dir.join("secrets.json");
+  fs::write(&path, r#"{ "client_secret": "file_secret", "password": "file_password" }"#).unwrap();
+  fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+  path.to_str().unwrap().to_string()
+}
+
+#[test]
+fn test_config_secrets_from_env() {
+  let env = pairs(&[("RRBOT_CLIENT_SECRET", "env_secret"), ("RRBOT_PASSWORD", "env_password")]);
+  let config = Config::layered(CONFIG_WITHOUT_SECRETS, Format::Json, env, &[]).unwrap();
+  assert_eq!(config.client_secret, "env_secret");
+  assert_eq!(config.password, "env_password");
+}
+
+#[test]
+fn test_config_missing_secrets() {
+  assert!(Config::layered(CONFIG_WITHOUT_SECRETS, Format::Json, Vec::new(), &[]).is_err());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_config_secrets_file() {
+  let dir = temp_dir("secrets-file");
+  let path = write_secrets(&dir, 0o600);
+  let config = Config::layered(CONFIG_WITHOUT_SECRETS, Format::Json, Vec::new(), &pairs(&[("secrets_filename", &path)])).unwrap();
+  assert_eq!(config.client_secret, "file_secret");
+  assert_eq!(config.password, "file_password");
+
+  // the environment still wins over the secrets file
+  let env = pairs(&[("RRBOT_SECRETS_FILENAME", &path), ("RRBOT_PASSWORD", "env_password")]);
+  let config = Config::layered(CONFIG_WITHOUT_SECRETS, Format::Json, env, &[]).unwrap();
+  assert_eq!(config.client_secret, "file_secret");
+  assert_eq!(config.password, "env_password");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_config_secrets_file_world_readable() {
+  let dir = temp_dir("secrets-world-readable");
+  let path = write_secrets(&dir, 0o644);
+  let result = Config::layered(CONFIG_WITHOUT_SECRETS, Format::Json, Vec::new(), &pairs(&[("secrets_filename", &path)]));
+  assert!(result.unwrap_err().to_string().contains("world-readable"));
+}
+
+#[test]
+fn test_config_credentials_directory() {
+  let dir = temp_dir("credentials");
+  fs::write(dir.join("client_secret"), "cred_secret\n").unwrap();
+  fs::write(dir.join("password"), "cred_password").unwrap();
+  let env = pairs(&[("CREDENTIALS_DIRECTORY", dir.to_str().unwrap())]);
+  let config = Config::layered(CONFIG_WITHOUT_SECRETS, Format::Json, env, &[]).unwrap();
+  assert_eq!(config.client_secret, "cred_secret");
+  assert_eq!(config.password, "cred_password");
+}
+
+#[test]
+fn test_config_debug_redacted() {
+  let config = Config::layered(LAYERED_CONFIG, Format::Json, Vec::new(), &pairs(&[("password", "hunter2")])).unwrap();
+  let logged = format!("{:?}", config);
+  assert!(!logged.contains("hunter2"));
+  assert!(logged.contains("username"));
+}
+
+fn problems(contents: &str, overrides: &[(&str, &str)]) -> Vec<String> {
+  match Config::layered(contents, Format::Json, Vec::new(), &pairs(overrides)) {
+    Ok(_) => Vec::new(),
+    Err(e) => e.downcast::<Invalid>().unwrap().problems,
+  }
+}
+
+#[test]
+fn test_config_valid() {
+  assert!(problems(LAYERED_CONFIG, &[]).is_empty());
+}
+
+#[test]
+fn test_config_reports_every_problem() {
+  let config = r#"
+    {
+      "client_id": "client_id",
+      "client_secret": "client_secret",
+      "username": 7,
+      "hot_take": 5000,
+      "inbox_db_filename": "inbox.db",
+      "posts_db_filename": "",
+      "comments_db_filename": "missing-directory/comments.db",
+      "sub": "",
+      "subreddit": "bodyweightfitness"
+    }
+  "#;
+  let problems = problems(config, &[]);
+  assert_eq!(problems, vec![
+    "subreddit: unknown field",
+    "username: expected a string, found 7",
+    "password: missing field; set it in the config, a secrets file, $CREDENTIALS_DIRECTORY or RRBOT_PASSWORD",
+    "sub: must not be empty",
+    "posts_db_filename: must not be empty",
+    "comments_db_filename: directory missing-directory does not exist",
+    "hot_take: 5000 is out of range, expected 1 to 1000",
+  ]);
+}
+
+#[test]
+fn test_config_sub_name() {
+  assert_eq!(problems(LAYERED_CONFIG, &[("sub", "r/bodyweightfitness")]), vec!["sub: leave out the r/ prefix, e.g. \"bodyweightfitness\""]);
+  assert_eq!(problems(LAYERED_CONFIG, &[("sub", "body weight")]), vec!["sub: \"body weight\" is not a subreddit name (letters, digits and _ only)"]);
+}
+
+#[test]
+fn test_config_ranges_and_urls() {
+  let problems = problems(LAYERED_CONFIG, &[("hot_take", "0"), ("max_pages", "0"), ("outbox_max_attempts", "0"), ("api_url", "oauth.reddit.com")]);
+  assert_eq!(problems, vec![
+    "hot_take: 0 is out of range, expected 1 to 1000",
+    "max_pages: 0 is out of range, expected 1 to 100",
+    "outbox_max_attempts: 0 is out of range, expected 1 to 100",
+    "api_url: \"oauth.reddit.com\" is not an http(s) URL",
+  ]);
+}
+
+#[test]
+fn test_config_hot_take_above_u8() {
+  let config = Config::layered(LAYERED_CONFIG, Format::Json, Vec::new(), &pairs(&[("hot_take", "500")])).unwrap();
+  assert_eq!(config.hot_take, 500);
+}
+
+#[test]
+fn test_config_invalid_display() {
+  let error = Config::layered(LAYERED_CONFIG, Format::Json, Vec::new(), &pairs(&[("sub", ""), ("hot_take", "0")])).unwrap_err();
+  assert_eq!(error.to_string(), "Invalid config (2 problems):\n  sub: must not be empty\n  hot_take: 0 is out of range, expected 1 to 1000");
+}
+
+fn assert_parsed(config: &Config) {
+  assert_eq!(config.client_id, "client_id");
+  assert_eq!(config.client_secret, "client_secret");
+  assert_eq!(config.username, "username");
+  assert_eq!(config.password, "password");
+  assert_eq!(config.hot_take, 50);
+  assert_eq!(config.inbox_db_filename, "inbox.db");
+  assert_eq!(config.posts_db_filename, "posts.db");
+  assert_eq!(config.comments_db_filename, "comments.db");
+  assert_eq!(config.sub, "sub");
+  assert_eq!(config.outbox_filename, "outbox.json");
+}
+
+#[test]
+fn test_config_parse_json() {
+  let config = Config::layered(LAYERED_CONFIG, Format::Json, Vec::new(), &[]).unwrap();
+  assert_parsed(&config);
+}
+
+#[test]
+fn test_config_parse_toml() {
+  let config = r#"
+    # reddit script app credentials
+    client_id = "client_id"
+    client_secret = "client_secret"
+    username = "username"
+    password = "password"
+
+    hot_take = 50 # posts to scan
+    inbox_db_filename = "inbox.db"
+    posts_db_filename = "posts.db"
+    comments_db_filename = "comments.db"
+    sub = "sub"
+  "#;
+
+  let config = Config::layered(config, Format::Toml, Vec::new(), &[]).unwrap();
+  assert_parsed(&config);
+}
+
+#[test]
+fn test_config_parse_yaml() {
+  let config = r#"
+    # reddit script app credentials
+    client_id: client_id
+    client_secret: client_secret
+    username: username
+    password: password
+
+    hot_take: 50 # posts to scan
+    inbox_db_filename: inbox.db
+    posts_db_filename: posts.db
+    comments_db_filename: comments.db
+    sub: sub
+  "#;
+
+  let config = Config::layered(config, Format::Yaml, Vec::new(), &[]).unwrap();
+  assert_parsed(&config);
+}
+
+#[test]
+fn test_config_toml_validated() {
+  let config = r#"
+    client_id = "client_id"
+    client_secret = "client_secret"
+    username = "username"
+    password = "password"
+    hot_take = 0
+    inbox_db_filename = "inbox.db"
+    posts_db_filename = "posts.db"
+    comments_db_filename = "comments.db"
+    sub = "sub"
+  "#;
+
+  let error = Config::layered(config, Format::Toml, Vec::new(), &[]).unwrap_err();
+  assert_eq!(error.downcast::<Invalid>().unwrap().problems, vec!["hot_take: 0 is out of range, expected 1 to 1000"]);
+}
+
+#[test]
+fn test_config_format_from_path() {
+  assert_eq!(Format::from_path("config.json"), Format::Json);
+  assert_eq!(Format::from_path("/etc/rrbot/config.toml"), Format::Toml);
+  assert_eq!(Format::from_path("config.yaml"), Format::Yaml);
+  assert_eq!(Format::from_path("config.yml"), Format::Yaml);
+  assert_eq!(Format::from_path("config"), Format::Json);
+}
+
+#[test]
+fn test_config_load_toml_file() {
+  let dir = temp_dir("load-toml");
+  let path = dir.join("config.toml");
+  fs::write(&path, "client_id = \"client_id\"\nusername = \"username\"\nhot_take = 50\ninbox_db_filename = \"inbox.db\"\nposts_db_filename = \"posts.db\"\ncomments_db_filename = \"comments.db\"\nsub = \"sub\"\n").unwrap();
+  let env = pairs(&[("RRBOT_CLIENT_SECRET", "client_secret"), ("RRBOT_PASSWORD", "password")]);
+  let config = Config::load(path.to_str().unwrap(), env, &[]).unwrap();
+  assert_parsed(&config);
+}
+
+const SUBS_CONFIG: &str = r#"
+  {
+    "client_id": "client_id",
+    "client_secret": "client_secret",
+    "username": "username",
+    "password": "password",
+    "hot_take": 50,
+    "inbox_db_filename": "inbox.db",
+    "posts_db_filename": "posts.db",
+    "comments_db_filename": "comments.db",
+    "subs": [
+      { "name": "bodyweightfitness" },
+      { "name": "flexibility", "hot_take": 10, "rules": ["rr"], "replies": { "rr": "See the wiki." }, "outbox_filename": "flex.json" }
+    ]
+  }
+"#;
+
+#[test]
+fn test_config_subs() {
+  let config = Config::new(SUBS_CONFIG).unwrap();
+  let subs = config.subs();
+  assert_eq!(subs.len(), 2);
+  assert_eq!(subs[0].name, "bodyweightfitness");
+  assert_eq!(subs[0].hot_take, 50);
+  assert_eq!(subs[0].posts_db_filename, "posts.bodyweightfitness.db");
+  assert_eq!(subs[0].comments_db_filename, "comments.bodyweightfitness.db");
+  assert_eq!(subs[0].outbox_filename, "outbox.bodyweightfitness.json");
+  assert_eq!(subs[0].rules[0].reply, *REPLY);
+  assert_eq!(subs[1].hot_take, 10);
+  assert_eq!(subs[1].outbox_filename, "flex.json");
+  assert_eq!(subs[1].rules[0].reply, "See the wiki.");
+}
+
+#[test]
+fn test_config_subs_single_entry() {
+  let config = LAYERED_CONFIG.replace("\"sub\": \"sub\"", "\"subs\": [{ \"name\": \"sub\" }]");
+  let subs = Config::new(&config).unwrap().subs();
+  assert_eq!(subs.len(), 1);
+  assert_eq!(subs[0].posts_db_filename, "posts.db");
+  assert_eq!(subs[0].comments_db_filename, "comments.db");
+  assert_eq!(subs[0].outbox_filename, "outbox.json");
+}
+
+#[test]
+fn test_config_subs_shared_db_left_behind() {
+  let dir = temp_dir("subs-shared-db");
+  let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+  fs::write(path("posts.db"), "p1\n").unwrap();
+  let config = SUBS_CONFIG
+    .replace("\"posts.db\"", &format!("{:?}", path("posts.db")))
+    .replace("\"comments.db\"", &format!("{:?}", path("comments.db")));
+  assert_eq!(problems(&config, &[]), vec![
+    format!("subs[0].posts_db_filename: {} exists but {} doesn't; rename it if it's this sub's, or create {} empty to start afresh", path("posts.db"), path("posts.bodyweightfitness.db"), path("posts.bodyweightfitness.db")),
+    format!("subs[1].posts_db_filename: {} exists but {} doesn't; rename it if it's this sub's, or create {} empty to start afresh", path("posts.db"), path("posts.flexibility.db"), path("posts.flexibility.db")),
+  ]);
+  fs::write(path("posts.bodyweightfitness.db"), "p1\n").unwrap();
+  fs::write(path("posts.flexibility.db"), "").unwrap();
+  assert!(problems(&config, &[]).is_empty());
+}
+
+#[test]
+fn test_config_single_sub() {
+  let subs = test_config().subs();
+  assert_eq!(subs.len(), 1);
+  assert_eq!(subs[0].name, "sub");
+  assert_eq!(subs[0].posts_db_filename, "posts.db");
+  assert_eq!(subs[0].outbox_filename, "outbox.json");
+}
+
+#[test]
+fn test_config_subs_flag() {
+  let config = LAYERED_CONFIG.replace(",\n    \"sub\": \"sub\"", "");
+  let config = Config::layered(&config, Format::Json, Vec::new(), &pairs(&[("subs", "a,b")])).unwrap();
+  let names = config.subs().into_iter().map(|sub| sub.name).collect::<Vec<_>>();
+  assert_eq!(names, vec!["a", "b"]);
+}
+
+#[test]
+fn test_config_secret_type_problems() {
+  let config = LAYERED_CONFIG.replace("\"password\": \"password\"", "\"password\": 123456");
+  assert_eq!(problems(&config, &[]), vec!["password: expected a string, found a number"]);
+}
+
+#[test]
+fn test_config_sub_and_subs() {
+  assert_eq!(problems(LAYERED_CONFIG, &[("subs", "a,b")]), vec![
+    "sub: set as well as subs, which would be scanned instead; list it under subs or remove it",
+  ]);
+}
+
+#[test]
+fn test_config_subs_problems() {
+  let config = SUBS_CONFIG.replace(r#"{ "name": "bodyweightfitness" }"#,
+    r#"{ "name": "flexibility", "sort": "new" }, { "hot_take": "ten" }, { "name": "a", "rules": ["nope"], "replies": { "nope": "x" } }"#);
+  assert_eq!(problems(&config, &[]), vec![
+    "subs[0].sort: unknown field",
+    "subs[1].name: missing field",
+    "subs[1].hot_take: expected a whole number, found \"ten\"",
+    "subs[2].rules[0]: unknown rule \"nope\", expected one of rr",
+    "subs[2].replies.nope: unknown rule, expected one of rr",
+    "subs[3].name: flexibility is listed more than once",
+  ]);
+}
+
+#[test]
+fn test_config_sorts() {
+  assert_eq!(test_config().subs()[0].sorts, vec![Sort::Hot]);
+  let config = Config::layered(LAYERED_CONFIG, Format::Json, pairs(&[("RRBOT_SORTS", "new, top:day")]), &[]).unwrap();
+  assert_eq!(config.subs()[0].sorts, vec![Sort::New, Sort::Top(Window::Day)]);
+  assert_eq!(Sort::Top(Window::Week).to_string(), "top:week");
+  assert_eq!(Sort::parse("rising"), Some(Sort::Rising));
+  assert_eq!(Sort::parse("top"), None);
+}
+
+#[test]
+fn test_config_sorts_problems() {
+  assert_eq!(problems(LAYERED_CONFIG, &[("sorts", "hot,best")]), vec![
+    "sorts[1]: unknown sort \"best\", expected one of hot, new, rising, top:hour, top:day, top:week",
+  ]);
+  let config = SUBS_CONFIG.replace(r#"{ "name": "bodyweightfitness" }"#, r#"{ "name": "bodyweightfitness", "sorts": [] }"#);
+  assert_eq!(problems(&config, &[]), vec!["subs[0].sorts: must list at least one sort"]);
+}
+
+#[test]
+fn test_config_user_agent() {
+  let config = test_config();
+  assert_eq!(config.user_agent(), format!("{}:rrbot:{} (by u/username)", std::env::consts::OS, env!("CARGO_PKG_VERSION")));
+  let overrides = pairs(&[("platform", "FreeBSD"), ("app_id", "org.example.helper"), ("app_version", "1.2"), ("contact", "someone")]);
+  let config = Config::layered(LAYERED_CONFIG, Format::Json, Vec::new(), &overrides).unwrap();
+  assert_eq!(config.user_agent(), "FreeBSD:org.example.helper:1.2 (by u/someone)");
+}
+
+#[test]
+fn test_config_user_agent_problems() {
+  let overrides = [("platform", ""), ("app_id", "my app"), ("app_version", "1:2"), ("contact", "u/someone")];
+  assert_eq!(problems(LAYERED_CONFIG, &overrides), vec![
+    "platform: must not be empty",
+    "app_id: \"my app\" must not contain colons or spaces",
+    "app_version: \"1:2\" must not contain colons or spaces",
+    "app_id: \"my app\" is not an app id (letters, digits, ., _ and - only), e.g. com.example.rrbot",
+    "contact: leave out the u/ prefix, e.g. \"someone\"",
+  ]);
+  assert_eq!(problems(LAYERED_CONFIG, &[("username", "me")]), vec!["username: \"me\" is not a reddit username (3 to 20 letters, digits, _ or -)"]);
+}
+
+#[test]
+fn test_end_to_end_user_agent() {
+  let reddit = FakeReddit::start();
+  let dir = temp_dir("end-to-end-user-agent");
+  let mut config = fake_config(&reddit, &dir);
+  config.contact = Some("someone".to_string());
+  Reddit::new(&config).unwrap();
+  assert_eq!(reddit.state.lock().unwrap().user_agent, config.user_agent());
+  assert!(config.user_agent().ends_with("(by u/someone)"));
+}
+
+#[test]
+fn test_end_to_end_reauthorizes_on_401() {
+  let reddit = FakeReddit::start();
+  reddit.add_post("p1", "RR?", "what is the rr?");
+  let dir = temp_dir("end-to-end-401");
+  let config = fake_config(&reddit, &dir);
+  let app = Reddit::new(&config).unwrap();
+  assert_eq!(app.get_posts("sub", Sort::Hot, 10).unwrap().len(), 1);
+  assert_eq!(reddit.state.lock().unwrap().token_requests, 1);
+
+  // the failed request is sent once more with a new token
+  reddit.revoke_token();
+  assert_eq!(app.get_posts("sub", Sort::Hot, 10).unwrap().len(), 1);
+  app.reply("t3_p1", "rr", "body").unwrap();
+  assert_eq!(reddit.state.lock().unwrap().token_requests, 2);
+  assert_eq!(reddit.replies().len(), 1);
+}
+
+#[test]
+fn test_end_to_end_reauthorizes_before_expiry() {
+  let reddit = FakeReddit::start();
+  reddit.state.lock().unwrap().expires_in = Some(30);
+  let dir = temp_dir("end-to-end-expiry");
+  let config = fake_config(&reddit, &dir);
+  let app = Reddit::new(&config).unwrap();
+
+  // a token within a minute of expiring is renewed before each request
+  app.get_posts("sub", Sort::Hot, 10).unwrap();
+  app.get_posts("sub", Sort::Hot, 10).unwrap();
+  assert_eq!(reddit.state.lock().unwrap().token_requests, 3);
+
+  reddit.state.lock().unwrap().expires_in = None;
+  app.get_posts("sub", Sort::Hot, 10).unwrap();
+  app.get_posts("sub", Sort::Hot, 10).unwrap();
+  assert_eq!(reddit.state.lock().unwrap().token_requests, 4);
+}
+
+#[test]
+fn test_bot_stops_after_current_reply() {
+  let mut bot = test_bot(&[("p1", "what is the rr?"), ("p2", "rr?")], &[("c1", "rr?")]);
+  bot.app.stop_after_reply = Some(Arc::clone(&bot.stop));
+  bot.run().unwrap();
+  assert_eq!(*bot.app.replies.borrow(), vec!["p1".to_string()]);
+  // what was done is still written
+  assert_eq!(bot.store.read("posts.db").unwrap(), "p1\n");
+  assert_eq!(bot.store.read("comments.db").unwrap(), "");
+}
+
+#[test]
+fn test_daemon_sleep_wakes_on_signal() {
+  let signals = Signals::new();
+  signals.reload.store(true, Ordering::SeqCst);
+  let start = Instant::now();
+  signals.sleep(Duration::from_secs(30));
+  assert!(start.elapsed() < Duration::from_secs(1));
+  assert!(signals.take_reload());
+  assert!(!signals.take_reload());
+
+  let start = Instant::now();
+  signals.sleep(Duration::from_millis(150));
+  assert!(start.elapsed() >= Duration::from_millis(150));
+}
+
+#[test]
+fn test_daemon_reload_keeps_the_interval() {
+  let signals = Signals::new();
+  let reload = Arc::clone(&signals.reload);
+  thread::spawn(move || {
+    thread::sleep(Duration::from_millis(50));
+    reload.store(true, Ordering::SeqCst);
+  });
+  let start = Instant::now();
+  let mut reloads = 0;
+  signals.wait(start + Duration::from_millis(300), || reloads += 1);
+  assert_eq!(reloads, 1);
+  assert!(start.elapsed() >= Duration::from_millis(300));
+
+  signals.stop.store(true, Ordering::SeqCst);
+  let start = Instant::now();
+  signals.wait(start + Duration::from_secs(30), || reloads += 1);
+  assert!(start.elapsed() < Duration::from_secs(1));
+  assert_eq!(reloads, 1);
+}
+
+#[test]
+fn test_daemon_sighup_sets_reload() {
+  let signals = Signals::register().unwrap();
+  signal_hook::low_level::raise(signal_hook::consts::SIGHUP).unwrap();
+  assert!(signals.take_reload());
+  assert!(!signals.stopping());
+}
+
+#[test]
+fn test_daemon_next_delay() {
+  assert_eq!(daemon::next_delay(300, 0), Duration::from_secs(300));
+  (0..100).for_each(|_| {
+    let delay = daemon::next_delay(300, 30);
+    assert!(delay >= Duration::from_secs(300) && delay <= Duration::from_secs(330));
+  });
+}
+
+#[test]
+fn test_lock_is_exclusive() {
+  let dir = temp_dir("lock-exclusive");
+  let path = dir.join("rrbot.lock").to_str().unwrap().to_string();
+  let lock = Lock::acquire(&path).unwrap();
+  assert_eq!(fs::read_to_string(&path).unwrap(), format!("{}\n", std::process::id()));
+
+  let error = Lock::acquire(&path).err().unwrap();
+  assert_eq!(crate::exit_code(&error), crate::EXIT_LOCKED);
+  assert_eq!(error.downcast::<Locked>().unwrap().pid, Some(std::process::id()));
+
+  // a holder that hasn't written its pid yet still holds the lock
+  fs::write(&path, "").unwrap();
+  let error = Lock::acquire(&path).err().unwrap();
+  assert_eq!(error.downcast::<Locked>().unwrap().pid, None);
+
+  drop(lock);
+  assert_eq!(fs::read_to_string(&path).unwrap(), "");
+  assert!(Lock::acquire(&path).is_ok());
+}
+
+#[test]
+fn test_lock_ignores_leftover_pid() {
+  let dir = temp_dir("lock-stale");
+  let path = dir.join("rrbot.lock").to_str().unwrap().to_string();
+  // a lock file left by a killed run isn't locked, whatever pid it names
+  fs::write(&path, "1\n").unwrap();
+  let _lock = Lock::acquire(&path).unwrap();
+  assert_eq!(fs::read_to_string(&path).unwrap(), format!("{}\n", std::process::id()));
+
+  fs::write(dir.join("garbled.lock"), "not a pid").unwrap();
+  assert!(Lock::acquire(dir.join("garbled.lock").to_str().unwrap()).is_ok());
+}
+
+#[test]
+fn test_exit_code() {
+  assert_eq!(crate::exit_code(&err_msg("anything else")), 1);
+  let error = Config::load("/nonexistent/config.json", Vec::new(), &[]).unwrap_err();
+  assert_eq!(crate::exit_code(&error), crate::EXIT_CONFIG);
+  let error = Breaker::new(RedditMock::new(None), 1).summary().into();
+  assert_eq!(crate::exit_code(&error), crate::EXIT_UNAVAILABLE);
+}
+
+#[test]
+fn test_exit_code_usage() {
+  let fixture = Some("fixture.json".to_string());
+  let options = crate::Options{ record: fixture.clone(), replay: fixture.clone(), ..Default::default() };
+  let error = crate::run(&options).unwrap_err();
+  assert_eq!(error.to_string(), "--record and --replay can't be used together (see rrbot --help)");
+  assert_eq!(crate::exit_code(&error), crate::EXIT_USAGE);
+  let options = crate::Options{ daemon: true, replay: fixture, ..Default::default() };
+  assert_eq!(crate::exit_code(&crate::run(&options).unwrap_err()), crate::EXIT_USAGE);
+}
+
+#[test]
+fn test_exit_code_auth() {
+  let reddit = FakeReddit::start();
+  let dir = temp_dir("exit-auth");
+  let mut config = fake_config(&reddit, &dir);
+  reddit.state.lock().unwrap().password = Some("hunter2".to_string());
+  let error = Reddit::new(&config).err().unwrap();
+  assert_eq!(error.to_string(), "Error authorizing: {\"error\":\"invalid_grant\"}");
+  assert_eq!(crate::exit_code(&error), crate::EXIT_AUTH);
+  config.password = "hunter2".to_string();
+  assert!(Reddit::new(&config).is_ok());
+
+  // nothing listening on the auth url is reddit being unavailable
+  let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+  config.auth_url = format!("http://{}", listener.local_addr().unwrap());
+  drop(listener);
+  let error = Reddit::new(&config).err().unwrap();
+  assert_eq!(crate::exit_code(&error), crate::EXIT_UNAVAILABLE);
+}
+
+#[test]
+fn test_exit_code_auth_mid_run() {
+  let reddit = FakeReddit::start();
+  reddit.add_post("p1", "RR?", "what is the rr?");
+  reddit.add_post("p2", "RR?", "what is the rr?");
+  let dir = temp_dir("exit-auth-mid-run");
+  let config = fake_config(&reddit, &dir);
+  let mut bot = Bot::new(Breaker::new(Reddit::new(&config).unwrap(), 5), FileStore, config);
+  // the token stops working and renewing it is turned away
+  reddit.state.lock().unwrap().password = Some("changed".to_string());
+  reddit.revoke_token();
+  let error = crate::run_once(&mut bot).unwrap_err();
+  assert_eq!(crate::exit_code(&error), crate::EXIT_AUTH);
+  assert_eq!(error.to_string(), "Error authorizing: {\"error\":\"invalid_grant\"}");
+  assert!(reddit.replies().is_empty());
+}
+
+#[test]
+fn test_summary_exit_code() {
+  assert_eq!(crate::summary_exit_code(None), crate::EXIT_OK);
+  let mut summary = Summary{ posts_scanned: 2, replies_sent: 1, ..Summary::default() };
+  assert_eq!(crate::summary_exit_code(Some(&summary)), crate::EXIT_OK);
+  summary.error("reply");
+  assert_eq!(crate::summary_exit_code(Some(&summary)), crate::EXIT_PARTIAL);
+  assert!(!summary.nothing_read());
+  let mut summary = Summary{ errors: vec![("listing".to_string(), 2)].into_iter().collect(), ..Summary::default() };
+  assert!(summary.nothing_read());
+  // one sort failed, but another was read and had nothing in it
+  summary.listings_read = 1;
+  assert!(!summary.nothing_read());
+}
+
+fn history_reply(id: &str, thread: &str, author: &str, at: u64) -> Reply {
+  Reply{ id: id.to_string(), thread: thread.to_string(), author: author.to_string(), sub: "sub".to_string(), rule: "rr".to_string(), at, comment: None }
+}
+
+#[test]
+fn test_throttle_per_run() {
+  let mut bot = test_bot(&[("p1", "rr?"), ("p2", "rr?"), ("p3", "rr?")], &[]);
+  bot.config.max_replies_per_run = Some(2);
+  bot.run().unwrap();
+  assert_eq!(*bot.app.replies.borrow(), vec!["p1".to_string(), "p2".to_string()]);
+
+  // skipped posts aren't marked as replied to, so the next run picks them up
+  bot.run().unwrap();
+  assert_eq!(bot.app.replies.borrow().len(), 3);
+}
+
+#[test]
+fn test_throttle_counts_retries() {
+  let mut bot = test_bot(&[("p1", "rr?")], &[]);
+  bot.config.max_replies_per_run = Some(1);
+  let mut outbox = Outbox::new(5, 60);
+  outbox.push(Entry::new("c9", Kind::Comment, "rr", "body"), &err_msg("503"), 0);
+  outbox.save(&mut bot.store, "outbox.json").unwrap();
+  let summary = bot.run().unwrap();
+  assert_eq!(*bot.app.replies.borrow(), vec!["c9".to_string()]);
+  assert_eq!(summary.skipped["max_replies_per_run"], 1);
+  let history = History::load(&bot.store, "history.json", crate::outbox::now()).unwrap();
+  assert_eq!(history.replies.iter().map(|reply| reply.id.as_str()).collect::<Vec<_>>(), vec!["c9"]);
+}
+
+#[test]
+fn test_throttle_per_thread() {
+  let mut bot = test_bot(&[("p1", "rr?")], &[("c1", "rr?"), ("c2", "what is the rr?")]);
+  bot.config.max_replies_per_thread = Some(2);
+  bot.run().unwrap();
+  assert_eq!(*bot.app.replies.borrow(), vec!["p1".to_string(), "c1".to_string()]);
+  assert_eq!(bot.store.read("comments.db").unwrap(), "c1\n");
+}
+
+#[test]
+fn test_throttle_per_author() {
+  let mut bot = test_bot(&[("p1", "Hello")], &[("c1", "rr?"), ("c2", "what is the rr?"), ("c3", "rr?")]);
+  bot.app.comments.children.iter_mut().for_each(|comment| comment.author = if comment.id == "c3" { "b" } else { "a" }.to_string());
+  bot.config.max_replies_per_author = Some(1);
+  bot.run().unwrap();
+  assert_eq!(*bot.app.replies.borrow(), vec!["c1".to_string(), "c3".to_string()]);
+}
+
+#[test]
+fn test_throttle_per_hour() {
+  let mut bot = test_bot(&[("p1", "rr?"), ("p2", "rr?")], &[]);
+  let now = crate::outbox::now();
+  let history = History{ replies: vec![
+    history_reply("x1", "t1", "a", now - 100),
+    history_reply("x2", "t2", "b", now - 200),
+    history_reply("x3", "t3", "c", now - 4000),
+  ], this_run: 0 };
+  history.save(&mut bot.store, "history.json").unwrap();
+  bot.config.max_replies_per_hour = Some(3);
+  bot.run().unwrap();
+  assert_eq!(*bot.app.replies.borrow(), vec!["p1".to_string()]);
+}
+
+#[test]
+fn test_throttle_limited() {
+  let now = 1_000_000;
+  let mut history = History{ replies: vec![history_reply("c1", "p1", "a", now - 10)], this_run: 0 };
+  let limits = Limits{ per_thread: Some(2), per_author: Some(1), ..Limits::default() };
+  assert_eq!(history.limited(&limits, "p1", "a", now), Some(("max_replies_per_author", 1)));
+  assert_eq!(history.limited(&limits, "p1", "b", now), None);
+  history.record(history_reply("c2", "p1", "b", now));
+  assert_eq!(history.limited(&limits, "p1", "c", now), Some(("max_replies_per_thread", 2)));
+  assert_eq!(history.limited(&limits, "p2", "c", now), None);
+  // replies to deleted accounts aren't all one author
+  assert_eq!(history.limited(&limits, "p2", "", now), None);
+  assert_eq!(history.limited(&Limits::default(), "p1", "a", now), None);
+}
+
+#[test]
+fn test_throttle_history_pruned() {
+  let mut store = MemoryStore::default();
+  let now = 10_000_000;
+  let history = History{ replies: vec![history_reply("old", "p1", "a", now - 8 * 24 * 60 * 60), history_reply("new", "p2", "a", now - 60)], this_run: 0 };
+  history.save(&mut store, "history.json").unwrap();
+  let history = History::load(&store, "history.json", now).unwrap();
+  assert_eq!(history.replies.iter().map(|reply| reply.id.as_str()).collect::<Vec<_>>(), vec!["new"]);
+}
+
+#[test]
+fn test_thread_dedup_off() {
+  let mut bot = test_bot(&[("p1", "rr?")], &[("c1", "rr?"), ("c2", "what is the rr?")]);
+  bot.run().unwrap();
+  assert_eq!(bot.app.replies.borrow().len(), 3);
+}
+
+#[test]
+fn test_thread_dedup_skip() {
+  let mut bot = test_bot(&[("p1", "Hello"), ("p2", "rr?")], &[("c1", "rr?"), ("c2", "what is the rr?")]);
+  bot.config.thread_dedup = ThreadDedup::Skip;
+  bot.run().unwrap();
+  // one reply per thread: c1 under p1; p2 under p2, whose comments are then skipped
+  assert_eq!(*bot.app.replies.borrow(), vec!["c1".to_string(), "p2".to_string()]);
+  // skipped comments are marked as handled
+  let mut comments = bot.store.read("comments.db").unwrap().lines().map(|id| id.to_string()).collect::<Vec<_>>();
+  comments.sort();
+  assert_eq!(comments, vec!["c1", "c2"]);
+
+  // and the history carries over to the next run
+  bot.store.write("comments.db", "").unwrap();
+  bot.run().unwrap();
+  assert_eq!(bot.app.replies.borrow().len(), 2);
+}
+
+#[test]
+fn test_thread_dedup_link() {
+  let mut bot = test_bot(&[("p1", "rr?")], &[("c1", "rr?")]);
+  bot.config.thread_dedup = ThreadDedup::Link;
+  bot.config.see_above_reply = "See {link}".to_string();
+  bot.run().unwrap();
+  assert_eq!(*bot.app.replies.borrow(), vec!["p1".to_string(), "c1".to_string()]);
+  assert_eq!(*bot.app.bodies.borrow(), vec![REPLY.to_string(), "See https://www.reddit.com/r/sub/comments/p1/_/r1/".to_string()]);
+}
+
+#[test]
+fn test_thread_dedup_sees_retries() {
+  // the retry drained from the outbox is the first reply in p1, so c2 links to it
+  let mut bot = test_bot(&[("p1", "Hello")], &[("c2", "rr?")]);
+  bot.config.thread_dedup = ThreadDedup::Link;
+  bot.config.see_above_reply = "See {link}".to_string();
+  let mut outbox = Outbox::new(5, 60);
+  outbox.push(Entry{ thread: "p1".to_string(), ..Entry::new("c1", Kind::Comment, "rr", "body") }, &err_msg("503"), 0);
+  outbox.save(&mut bot.store, "outbox.json").unwrap();
+  bot.run().unwrap();
+  assert_eq!(*bot.app.replies.borrow(), vec!["c1".to_string(), "c2".to_string()]);
+  assert_eq!(bot.app.bodies.borrow()[1], "See https://www.reddit.com/r/sub/comments/p1/_/r1/");
+}
+
+#[test]
+fn test_thread_dedup_link_without_comment_id() {
+  // a dry run has no comment to link to, so later matches are skipped
+  let mut bot = test_bot(&[("p1", "rr?")], &[("c1", "rr?")]);
+  bot.config.thread_dedup = ThreadDedup::Link;
+  let history = History{ replies: vec![history_reply("p1", "p1", "a", crate::outbox::now())], this_run: 0 };
+  history.save(&mut bot.store, "history.json").unwrap();
+  bot.run().unwrap();
+  assert!(bot.app.replies.borrow().is_empty());
+}
+
+#[test]
+fn test_thread_dedup_problems() {
+  assert_eq!(problems(LAYERED_CONFIG, &[("thread_dedup", "once"), ("see_above_reply", "See above")]), vec![
+    "thread_dedup: unknown mode \"once\", expected one of off, skip, link",
+    "see_above_reply: must contain {link}, where the link to the first reply goes",
+  ]);
+  let config = Config::layered(LAYERED_CONFIG, Format::Json, pairs(&[("RRBOT_THREAD_DEDUP", "link")]), &[]).unwrap();
+  assert_eq!(config.thread_dedup, ThreadDedup::Link);
+}
+
+#[test]
+fn test_end_to_end_reply_returns_comment_id() {
+  let reddit = FakeReddit::start();
+  let dir = temp_dir("end-to-end-comment-id");
+  let app = Reddit::new(&fake_config(&reddit, &dir)).unwrap();
+  assert_eq!(app.reply("t3_p1", "rr", "body").unwrap(), Some("r0".to_string()));
+}
+
+#[test]
+fn test_blackout_periods() {
+  assert_eq!(Period::parse("22:00-06:00"), Some(Period::Daily{ start: 22 * 60, end: 6 * 60 }));
+  assert_eq!(Period::parse("1970-01-02T00:00/1970-01-02T01:30Z"), Some(Period::Once{ start: 86400, end: 86400 + 5400 }));
+  assert_eq!(Period::parse("2026-10-20T18:00/2026-10-20T22:00"), Some(Period::Once{ start: 1792519200, end: 1792533600 }));
+  assert_eq!(Period::parse("24:00-06:00"), None);
+  assert_eq!(Period::parse("06:00-06:00"), None);
+  assert_eq!(Period::parse("2026-10-20T22:00/2026-10-20T18:00"), None);
+  assert_eq!(Period::parse("tonight"), None);
+
+  let day = 20000 * 86400;
+  let overnight = Period::parse("22:00-06:00").unwrap();
+  assert!(overnight.contains(day + 23 * 3600));
+  assert!(overnight.contains(day + 5 * 3600 + 59 * 60));
+  assert!(!overnight.contains(day + 6 * 3600));
+  assert!(!overnight.contains(day + 12 * 3600));
+  let lunch = Period::parse("12:00-13:00").unwrap();
+  assert!(lunch.contains(day + 12 * 3600 + 30 * 60));
+  assert!(!lunch.contains(day + 13 * 3600));
+}
+
+fn held_ids(bot: &Bot<RedditMock, MemoryStore>) -> Vec<String> {
+  let review = Review::load(&bot.store, "held.json", crate::outbox::now()).unwrap();
+  review.held.iter().map(|held| format!("{} ({})", held.id, held.reason)).collect()
+}
+
+#[test]
+fn test_blackout_quiet_hours() {
+  let mut bot = test_bot(&[("p1", "rr?")], &[("c1", "rr?")]);
+  bot.config.quiet_hours = vec!["00:00-23:59".to_string(), "23:59-00:00".to_string()];
+  bot.run().unwrap();
+  assert!(bot.app.replies.borrow().is_empty());
+  assert_eq!(bot.store.read("posts.db").unwrap(), "");
+  assert_eq!(held_ids(&bot).len(), 2);
+  assert!(held_ids(&bot)[0].starts_with("p1 (quiet hours "));
+
+  // held candidates are answered once the window closes, and seen again they aren't held twice
+  bot.run().unwrap();
+  assert_eq!(held_ids(&bot).len(), 2);
+  bot.config.quiet_hours = Vec::new();
+  bot.run().unwrap();
+  assert_eq!(*bot.app.replies.borrow(), vec!["p1".to_string(), "c1".to_string()]);
+}
+
+#[test]
+fn test_blackout_posts_and_flairs() {
+  let mut bot = test_bot(&[("p1", "rr?")], &[]);
+  bot.app.posts.get_mut("sub/hot").unwrap().extend(vec![
+    json!({ "kind": "t3", "data": { "id": "p2", "title": "AMA", "selftext": "rr?", "link_flair_text": "AMA" } }),
+    json!({ "kind": "t3", "data": { "id": "p3", "title": "title", "selftext": "rr?", "link_flair_text": "Question" } }),
+  ]);
+  bot.config.blackout_posts = vec!["p1".to_string()];
+  bot.config.blackout_flairs = vec!["ama".to_string()];
+  bot.run().unwrap();
+  assert_eq!(*bot.app.replies.borrow(), vec!["p3".to_string()]);
+  assert_eq!(held_ids(&bot), vec!["p1 (blackout post p1)", "p2 (blackout flair AMA)"]);
+}
+
+#[test]
+fn test_blackout_problems() {
+  let problems = problems(LAYERED_CONFIG, &[("quiet_hours", "22:00-06:00,late"), ("blackout_flairs", "AMA, Event")]);
+  assert_eq!(problems, vec!["quiet_hours[1]: \"late\" is not a UTC window, e.g. \"22:00-06:00\" or \"2026-10-20T18:00/2026-10-20T22:00\""]);
+  let config = Config::layered(LAYERED_CONFIG, Format::Json, Vec::new(), &pairs(&[("blackout_flairs", "AMA, Event")])).unwrap();
+  assert_eq!(config.blackout_flairs, vec!["AMA", "Event"]);
+}
+
+#[test]
+fn test_breaker_opens_and_half_opens() {
+  let mut mock = RedditMock::new(None);
+  mock.fail_replies = true;
+  let breaker = Breaker::new(mock, 2);
+  assert!(breaker.reply("p1", "rr", "body").is_err());
+  assert_eq!(breaker.circuit(), Circuit::Closed);
+  assert!(breaker.reply("p2", "rr", "body").is_err());
+  assert_eq!(breaker.circuit(), Circuit::Open);
+  // open, calls fail without reaching reddit
+  assert!(breaker.get_posts("sub", Sort::Hot, 10).is_err());
+  assert_eq!(breaker.summary().to_string(), "Circuit open after 2 consecutive failed reddit calls (last: reply failed); ended the run, skipping 1 calls. The next run tries again.");
+
+  // the next run lets a call through; one failure opens it again
+  breaker.begin_run();
+  assert_eq!(breaker.circuit(), Circuit::HalfOpen);
+  assert!(breaker.reply("p3", "rr", "body").is_err());
+  assert_eq!(breaker.circuit(), Circuit::Open);
+
+  // and a success closes it
+  let mut breaker = breaker;
+  breaker.inner.fail_replies = false;
+  breaker.begin_run();
+  assert_eq!(breaker.reply("p4", "rr", "body").unwrap(), Some("r1".to_string()));
+  assert_eq!(breaker.circuit(), Circuit::Closed);
+}
+
+#[test]
+fn test_breaker_ignores_refused_calls() {
+  let reddit = FakeReddit::start();
+  reddit.state.lock().unwrap().reply_error = Some("THREAD_LOCKED".to_string());
+  let dir = temp_dir("breaker-refused");
+  let config = fake_config(&reddit, &dir);
+  let breaker = Breaker::new(Reddit::new(&config).unwrap(), 1);
+
+  // reddit answered each time, so it's up
+  let error = breaker.reply("t1_c1", "rr", "body").unwrap_err();
+  assert_eq!(error.downcast_ref::<Rejected>().unwrap().code, "THREAD_LOCKED");
+  assert!(breaker.reply("t1_c2", "rr", "body").is_err());
+  assert_eq!(breaker.circuit(), Circuit::Closed);
+  assert_eq!(breaker.summary().failures, 0);
+
+  // a 5xx counts
+  reddit.state.lock().unwrap().reply_status = Some(503);
+  assert!(breaker.reply("t1_c3", "rr", "body").is_err());
+  assert_eq!(breaker.circuit(), Circuit::Open);
+}
+
+#[test]
+fn test_breaker_state_carries_over() {
+  let mut store = MemoryStore::default();
+  let mut mock = RedditMock::new(None);
+  mock.fail_replies = true;
+  let breaker = Breaker::new(mock, 2);
+  breaker.load(&store, "breaker.json").unwrap();
+  assert_eq!(breaker.circuit(), Circuit::Closed);
+  assert!(breaker.reply("p1", "rr", "body").is_err());
+  assert!(breaker.reply("p2", "rr", "body").is_err());
+  breaker.save(&mut store, "breaker.json").unwrap();
+
+  // a new process, as cron starts for each run, tries one call and opens again on a failure
+  let mut mock = RedditMock::new(None);
+  mock.fail_replies = true;
+  let breaker = Breaker::new(mock, 2);
+  breaker.load(&store, "breaker.json").unwrap();
+  assert_eq!(breaker.circuit(), Circuit::Open);
+  breaker.begin_run();
+  assert!(breaker.reply("p3", "rr", "body").is_err());
+  assert_eq!(breaker.circuit(), Circuit::Open);
+  assert_eq!(breaker.summary().failures, 3);
+}
+
+#[test]
+fn test_run_once_saves_breaker() {
+  let bot = test_bot(&[("p1", "what is the rr?")], &[]);
+  let mut bot = Bot::new(Breaker::new(bot.app, 1), bot.store, bot.config);
+  bot.app.inner.fail_replies = true;
+  let error = crate::run_once(&mut bot).unwrap_err();
+  assert_eq!(crate::exit_code(&error), crate::EXIT_UNAVAILABLE);
+  let saved: serde_json::Value = serde_json::from_str(&bot.store.read("breaker.json").unwrap()).unwrap();
+  assert_eq!(saved["circuit"], "open");
+
+  bot.app.inner.fail_replies = false;
+  let mut bot = Bot::new(Breaker::new(bot.app.inner, 1), bot.store, bot.config);
+  crate::run_once(&mut bot).unwrap();
+  assert_eq!(bot.app.circuit(), Circuit::Closed);
+  assert_eq!(bot.store.read("breaker.json").unwrap(), serde_json::to_string_pretty(&json!({ "circuit": "closed", "failures": 0, "last_error": "reply failed" })).unwrap());
+}
+
+#[test]
+fn test_bot_ends_run_when_circuit_opens() {
+  let bot = test_bot(&[("p1", "what is the rr?"), ("p2", "rr?")], &[("c1", "rr?")]);
+  let mut bot = Bot::new(Breaker::new(bot.app, 2), bot.store, bot.config);
+  bot.app.inner.fail_replies = true;
+  bot.run().unwrap();
+  assert_eq!(bot.app.circuit(), Circuit::Open);
+  // p2 was never tried; what failed is queued and the stores are written
+  let outbox = Outbox::load(&bot.store, "outbox.json", 5, 60).unwrap();
+  assert!(outbox.contains("p1"));
+  assert!(outbox.contains("c1"));
+  assert!(!outbox.contains("p2"));
+  assert_eq!(bot.store.read("posts.db").unwrap(), "");
+
+  // the next run tries reddit again
+  bot.app.inner.fail_replies = false;
+  bot.run().unwrap();
+  assert_eq!(bot.app.circuit(), Circuit::Closed);
+  assert!(bot.app.inner.replies.borrow().contains(&"p2".to_string()));
+}
+
+#[test]
+fn test_breaker_threshold_problems() {
+  let problems = problems(LAYERED_CONFIG, &[("breaker_threshold", "0")]);
+  assert_eq!(problems, vec!["breaker_threshold: 0 is out of range, expected 1 to 1000"]);
+  let config = Config::layered(LAYERED_CONFIG, Format::Json, Vec::new(), &Vec::new()).unwrap();
+  assert_eq!(config.breaker_threshold, 5);
+}
+
+#[test]
+fn test_log_filter() {
+  let filter = Filter::parse("warn, reddit=debug,rrbot::reddit::auth=trace,ureq=info").unwrap();
+  assert_eq!(filter.level("rrbot::bot"), log::LevelFilter::Warn);
+  assert_eq!(filter.level("rrbot::reddit"), log::LevelFilter::Debug);
+  assert_eq!(filter.level("rrbot::reddit::auth"), log::LevelFilter::Trace);
+  assert_eq!(filter.level("rrbot::redditor"), log::LevelFilter::Warn);
+  assert_eq!(filter.level("ureq::unit"), log::LevelFilter::Info);
+  assert_eq!(Filter::parse("").unwrap(), Filter::default());
+  assert_eq!(Filter::parse("bot=loud").unwrap_err(), "unknown level \"loud\", expected one of off, error, warn, info, debug, trace");
+}
+
+#[test]
+fn test_log_lines() {
+  let _sub = logging::context("sub", "sub");
+  {
+    let _post = logging::context("post", "p1");
+    assert_eq!(logging::fields(), vec![("sub", "sub".to_string()), ("post", "p1".to_string())]);
+    let line = logging::format_line(LogFormat::Json, log::Level::Info, "rrbot::bot", "Replying", &logging::fields(), 1.5);
+    let line: serde_json::Value = serde_json::from_str(&line).unwrap();
+    assert_eq!(line, json!({ "time": 1.5, "level": "info", "module": "rrbot::bot", "message": "Replying", "sub": "sub", "post": "p1" }));
+  }
+  assert_eq!(logging::fields(), vec![("sub", "sub".to_string())]);
+  assert_eq!(logging::format_line(LogFormat::Text, log::Level::Warn, "rrbot::bot", "Error", &logging::fields(), 1.5), "WARN  Error");
+}
+
+#[test]
+fn test_log_problems() {
+  let problems = problems(LAYERED_CONFIG, &[("log_level", "info,reddit=chatty"), ("log_format", "xml")]);
+  assert_eq!(problems, vec![
+    "log_level: unknown level \"chatty\", expected one of off, error, warn, info, debug, trace",
+    "log_format: unknown format \"xml\", expected one of text, json",
+  ]);
+  let config = Config::layered(LAYERED_CONFIG, Format::Json, Vec::new(), &pairs(&[("log_format", "json")])).unwrap();
+  assert_eq!(config.log_format, LogFormat::Json);
+  assert_eq!(config.log_level, "info");
+}
+
+#[test]
+fn test_bot_run_summary() {
+  // the mock gives every post the same comments, so c1 matches again under p2
+  let mut bot = test_bot(&[("p1", "what is the rr?"), ("p2", "Hello")], &[("c1", "rr?"), ("c2", "Hello World")]);
+  bot.config.summary_filename = Some("summary.json".to_string());
+  let summary = bot.run().unwrap();
+  assert_eq!((summary.subs, summary.posts_scanned, summary.comments_scanned), (1, 2, 4));
+  assert_eq!(summary.matches, vec![("rr".to_string(), 3)].into_iter().collect());
+  assert_eq!((summary.replies_sent, summary.retries_sent, summary.queued), (2, 0, 0));
+  assert_eq!(summary.skipped, vec![("already_replied".to_string(), 1)].into_iter().collect());
+  assert!(summary.errors.is_empty());
+  assert_eq!(summary.phases.keys().collect::<Vec<_>>(), vec!["listings", "outbox", "save", "threads"]);
+  let written: serde_json::Value = serde_json::from_str(&bot.store.read("summary.json").unwrap()).unwrap();
+  assert_eq!(written["replies_sent"], 2);
+  assert_eq!(written["skipped"]["already_replied"], 1);
+
+  // failed replies are errors, and their matches elsewhere are skipped as queued
+  let mut bot = test_bot(&[("p1", "what is the rr?"), ("p2", "Hello")], &[("c1", "rr?")]);
+  bot.app.fail_replies = true;
+  bot.config.max_replies_per_run = Some(5);
+  let summary = bot.run().unwrap();
+  assert_eq!(summary.errors, vec![("reply".to_string(), 2)].into_iter().collect());
+  assert_eq!(summary.skipped, vec![("queued".to_string(), 1)].into_iter().collect());
+  assert_eq!((summary.replies_sent, summary.queued), (0, 2));
+  assert!(bot.store.read("summary.json").is_none());
+}
+
+#[test]
+fn test_summary_display() {
+  let mut summary = Summary{ subs: 1, posts_scanned: 2, comments_scanned: 4, replies_sent: 2, retries_sent: 1, elapsed_secs: 1.5, ..Summary::default() };
+  summary.matched("rr");
+  summary.skip("max_replies_per_run");
+  summary.skip("max_replies_per_run");
+  assert_eq!(summary.to_string(), "scanned 2 posts and 4 comments in 1 subs; 1 matches (rr: 1); sent 2 replies (1 retried), 0 queued; skipped 2 (max_replies_per_run: 2); 0 errors; took 1.50s");
+}
+
+#[test]
+fn test_dry_run_writes_no_summary() {
+  let mut bot = test_bot(&[("p1", "what is the rr?")], &[]);
+  bot.config.summary_filename = Some("summary.json".to_string());
+  bot.dry_run = true;
+  assert_eq!(bot.run().unwrap().replies_sent, 1);
+  assert!(bot.store.files.is_empty());
+}
+
+#[test]
+fn test_metrics_render() {
+  let mut registry = Registry::default();
+  assert_eq!(registry.render(), "");
+  registry.inc("rrbot_replies_total", &[("rule", "rr")]);
+  registry.inc("rrbot_replies_total", &[("rule", "rr")]);
+  registry.set("rrbot_store_entries", &[("store", "posts_db"), ("sub", "say \"hi\"")], 3.0);
+  registry.observe("rrbot_api_request_duration_seconds", &[("endpoint", "listing")], 0.3);
+  registry.observe("rrbot_scan_duration_seconds", &[], 12.0);
+  let text = registry.render();
+  assert!(text.contains("# TYPE rrbot_replies_total counter\nrrbot_replies_total{rule=\"rr\"} 2\n"));
+  assert!(text.contains("rrbot_store_entries{store=\"posts_db\",sub=\"say \\\"hi\\\"\"} 3\n"));
+  assert!(text.contains("rrbot_api_request_duration_seconds_bucket{endpoint=\"listing\",le=\"0.25\"} 0\n"));
+  assert!(text.contains("rrbot_api_request_duration_seconds_bucket{endpoint=\"listing\",le=\"0.5\"} 1\n"));
+  assert!(text.contains("rrbot_api_request_duration_seconds_bucket{endpoint=\"listing\",le=\"+Inf\"} 1\n"));
+  assert!(text.contains("rrbot_api_request_duration_seconds_count{endpoint=\"listing\"} 1\n"));
+  assert!(text.contains("rrbot_scan_duration_seconds_bucket{le=\"30\"} 1\nrrbot_scan_duration_seconds_bucket{le=\"60\"} 1\n"));
+  assert!(text.contains("rrbot_scan_duration_seconds_sum 12\n"));
+  assert!(!text.contains("rrbot_matches_total"));
+}
+
+#[test]
+fn test_metrics_count_api_requests() {
+  let reddit = FakeReddit::start();
+  reddit.add_post("p1", "RR?", "what is the rr?");
+  reddit.state.lock().unwrap().reply_status = Some(503);
+  let dir = temp_dir("metrics-api");
+  let mut config = fake_config(&reddit, &dir);
+  config.metrics_textfile = Some(dir.join("rrbot.prom").to_str().unwrap().to_string());
+  let mut bot = Bot::new(Reddit::new(&config).unwrap(), FileStore, config);
+  bot.run().unwrap();
+  let text = metrics::render();
+  assert!(text.contains("rrbot_api_requests_total{endpoint=\"access_token\",status=\"200\"}"));
+  assert!(text.contains("rrbot_api_requests_total{endpoint=\"listing\",status=\"200\"}"));
+  assert!(text.contains("rrbot_api_requests_total{endpoint=\"comment\",status=\"503\"}"));
+  assert!(text.contains("rrbot_reply_errors_total{rule=\"rr\"}"));
+  assert!(text.contains("rrbot_store_entries{store=\"outbox\",sub=\"sub\"}"));
+
+  metrics::write_textfile(bot.config.metrics_textfile.as_ref().unwrap()).unwrap();
+  let written = fs::read_to_string(dir.join("rrbot.prom")).unwrap();
+  assert!(written.contains("# TYPE rrbot_api_requests_total counter"));
+  assert!(!dir.join("rrbot.prom.partial").exists());
+}
+
+#[test]
+fn test_run_writes_metrics_when_startup_fails() {
+  let reddit = FakeReddit::start();
+  reddit.state.lock().unwrap().password = Some("hunter2".to_string());
+  let dir = temp_dir("metrics-startup");
+  let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+  let mut config = fake_config_json(&reddit, &dir);
+  config["metrics_textfile"] = json!(path("rrbot.prom"));
+  fs::write(dir.join("config.json"), config.to_string()).unwrap();
+
+  let error = crate::run(&crate::Options{ config: Some(path("config.json")), ..Default::default() }).unwrap_err();
+  assert_eq!(crate::exit_code(&error), crate::EXIT_AUTH);
+  let written = fs::read_to_string(dir.join("rrbot.prom")).unwrap();
+  assert!(written.contains("rrbot_api_requests_total{endpoint=\"access_token\""));
+}
+
+#[test]
+fn test_metrics_endpoint() {
+  metrics::inc("rrbot_matches_total", &[("rule", "endpoint-test")]);
+  let port = server::serve(0, Arc::new(Mutex::new(Health::default()))).unwrap();
+  let response = ureq::get(&format!("http://127.0.0.1:{}/metrics", port)).call().unwrap();
+  assert_eq!(response.status(), 200);
+  assert!(response.into_string().unwrap().contains("rrbot_matches_total{rule=\"endpoint-test\"} "));
+  match ureq::get(&format!("http://127.0.0.1:{}/other", port)).call() {
+    Err(ureq::Error::Status(404, _)) => (),
+    other => panic!("expected a 404, got {:?}", other.map(|response| response.status())),
+  }
+}
+
+#[test]
+fn test_server_not_held_up_by_idle_clients() {
+  let port = server::serve(0, Arc::new(Mutex::new(Health::default()))).unwrap();
+  // connected, but never sends a request
+  let _idle = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+  let start = Instant::now();
+  let response = ureq::get(&format!("http://127.0.0.1:{}/metrics", port)).call().unwrap();
+  assert_eq!(response.status(), 200);
+  assert!(start.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn test_metrics_problems() {
+  let problems = problems(LAYERED_CONFIG, &[("metrics_port", "0"), ("metrics_textfile", "rrbot.txt")]);
+  assert_eq!(problems, vec![
+    "metrics_port: 0 is out of range, expected 1 to 65535",
+    "metrics_textfile: must end in .prom, or the textfile collector won't read it",
+  ]);
+  let config = Config::layered(LAYERED_CONFIG, Format::Json, Vec::new(), &pairs(&[("metrics_port", "9184")])).unwrap();
+  assert_eq!(config.metrics_port, Some(9184));
+}
+
+#[test]
+fn test_health_report() {
+  let mut health = Health{ started: 1000, max_age_secs: 600, ..Health::default() };
+  // judged from the start until the first scan
+  assert!(health.report(1500).0);
+  let (healthy, report) = health.report(1700);
+  assert!(!healthy);
+  assert_eq!(report["status"], "stale");
+  assert_eq!(report["last_scan"], serde_json::Value::Null);
+  assert_eq!(report["authenticated"], serde_json::Value::Null);
+
+  health.last_scan = Some(2000);
+  health.authenticated = Some(Err("401 Unauthorized".to_string()));
+  health.circuit = Circuit::HalfOpen;
+  health.outbox = 3;
+  let (healthy, report) = health.report(2500);
+  assert!(healthy);
+  assert_eq!(report, json!({
+    "status": "ok",
+    "last_scan": 2000,
+    "last_scan_age_secs": 500,
+    "max_age_secs": 600,
+    "last_error": null,
+    "authenticated": false,
+    "auth_error": "401 Unauthorized",
+    "circuit": "half-open",
+    "outbox": 3,
+  }));
+}
+
+#[test]
+fn test_healthz_endpoint() {
+  // a health of its own, so the runs other tests make can't change what it reports
+  let health = Arc::new(Mutex::new(Health{ last_scan: Some(crate::outbox::now()), max_age_secs: 600, ..Health::default() }));
+  let port = server::serve(0, Arc::clone(&health)).unwrap();
+  let url = format!("http://127.0.0.1:{}/healthz", port);
+  let response = ureq::get(&url).call().unwrap();
+  assert_eq!(response.status(), 200);
+  assert_eq!(response.into_json::<serde_json::Value>().unwrap()["status"], "ok");
+
+  health.lock().unwrap().last_scan = Some(crate::outbox::now() - 601);
+  match ureq::get(&url).call() {
+    Err(ureq::Error::Status(503, response)) => assert_eq!(response.into_json::<serde_json::Value>().unwrap()["status"], "stale"),
+    other => panic!("expected a 503, got {:?}", other.map(|response| response.status())),
+  }
+}
+
+#[test]
+fn test_health_max_age() {
+  let mut config = Config::new(LAYERED_CONFIG).unwrap();
+  assert_eq!(config.health_max_age(), 3 * (300 + 30));
+  config.health_max_age_secs = Some(120);
+  assert_eq!(config.health_max_age(), 120);
+  let problems = problems(LAYERED_CONFIG, &[("health_max_age_secs", "5")]);
+  assert_eq!(problems, vec!["health_max_age_secs: 5 is out of range, expected 60 to 604800"]);
+}
@@ -0,0 +1,95 @@
+use crate::config::Config;
+use crate::store::Store;
+use failure::Error;
+use serde::Deserialize;
+use serde::Serialize;
+
+const HOUR: u64 = 60 * 60;
+const DAY: u64 = 24 * HOUR;
+// replies older than this no longer count against any limit and are dropped from the history
+const KEEP: u64 = 7 * DAY;
+
+// a reply the bot made: the thing replied to, the post it's in and whose it was
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Reply {
+  pub id: String,
+  pub thread: String,
+  pub author: String,
+  pub sub: String,
+  pub rule: String,
+  pub at: u64,
+  // the bot's comment, when the app reports one
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub comment: Option<String>,
+}
+
+// caps on replies; None is no cap
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Limits {
+  pub per_run: Option<u32>,
+  // in the last hour
+  pub per_hour: Option<u32>,
+  // in one post and its comments
+  pub per_thread: Option<u32>,
+  // to one author in the last day
+  pub per_author: Option<u32>,
+}
+
+impl Limits {
+  pub fn new(config: &Config) -> Limits {
+    Limits{
+      per_run: config.max_replies_per_run,
+      per_hour: config.max_replies_per_hour,
+      per_thread: config.max_replies_per_thread,
+      per_author: config.max_replies_per_author,
+    }
+  }
+}
+
+#[derive(Default)]
+pub struct History {
+  pub replies: Vec<Reply>,
+  // replies made since the history was loaded
+  pub this_run: u32,
+}
+
+impl History {
+  pub fn load<S: Store>(store: &S, name: &str, now: u64) -> Result<History, Error> {
+    let mut replies: Vec<Reply> = match store.read(name) {
+      Some(contents) => serde_json::from_str(&contents)?,
+      None => Vec::new(),
+    };
+    replies.retain(|reply| reply.at + KEEP > now);
+    Ok(History{ replies, this_run: 0 })
+  }
+
+  pub fn save<S: Store>(&self, store: &mut S, name: &str) -> Result<(), Error> {
+    store.write(name, &serde_json::to_string_pretty(&self.replies)?)
+  }
+
+  // the first limit a reply to author in thread would go over, and its value
+  pub fn limited(&self, limits: &Limits, thread: &str, author: &str, now: u64) -> Option<(&'static str, u32)> {
+    let count = |since: u64, matches: &dyn Fn(&Reply) -> bool| {
+      self.replies.iter().filter(|reply| reply.at + since > now && matches(reply)).count() as u32
+    };
+    let checks = [
+      (limits.per_run, self.this_run, "max_replies_per_run"),
+      (limits.per_hour, count(HOUR, &|_| true), "max_replies_per_hour"),
+      (limits.per_thread, count(KEEP, &|reply| reply.thread == thread), "max_replies_per_thread"),
+      (limits.per_author, count(DAY, &|reply| !author.is_empty() && reply.author == author), "max_replies_per_author"),
+    ];
+    checks.iter()
+      .find(|(limit, made, _)| limit.is_some_and(|limit| *made >= limit))
+      .map(|(limit, _, name)| (*name, limit.unwrap_or_default()))
+  }
+
+  // the bot's first reply in thread for rule
+  pub fn first_reply(&self, thread: &str, rule: &str) -> Option<&Reply> {
+    self.replies.iter().find(|reply| reply.thread == thread && reply.rule == rule)
+  }
+
+  pub fn record(&mut self, reply: Reply) {
+    self.replies.push(reply);
+    self.this_run += 1;
+  }
+}
@@ -0,0 +1,348 @@
+use crate::blackout::Period;
+use crate::config::namespaced;
+use crate::config::Kind;
+use crate::config::FIELDS;
+use crate::config::REQUIRED;
+use crate::config::SECRETS;
+use crate::config::LOG_FORMATS;
+use crate::config::THREAD_DEDUP;
+use crate::logging::Filter;
+use crate::post::RULES;
+use crate::reddit::SORTS;
+use serde_json::Map;
+use serde_json::Value;
+use std::fmt;
+use std::path::Path;
+
+// every problem found in a config, each starting with the path of the field at fault
+#[derive(Debug)]
+pub struct Invalid {
+  pub problems: Vec<String>,
+}
+
+impl fmt::Display for Invalid {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "Invalid config ({} problem{}):", self.problems.len(), if self.problems.len() == 1 { "" } else { "s" })?;
+    self.problems.iter().try_for_each(|problem| write!(f, "\n  {}", problem))
+  }
+}
+
+impl std::error::Error for Invalid {}
+
+pub fn validate(config: &Value) -> Vec<String> {
+  let fields = match config.as_object() {
+    Some(fields) => fields,
+    None => return vec!["config: expected a JSON object".to_string()],
+  };
+  let mut problems = Vec::new();
+  check_fields(fields, &mut problems);
+  if let Some(sub) = fields.get("sub").and_then(Value::as_str) {
+    check_sub_name("sub", sub, &mut problems);
+  }
+  check_subs(fields, &mut problems);
+  for name in &["inbox_db_filename", "posts_db_filename", "comments_db_filename", "outbox_filename", "history_filename", "held_filename", "breaker_filename", "lock_filename", "summary_filename", "metrics_textfile"] {
+    check_path(name, fields.get(*name), &mut problems);
+  }
+  check_range("hot_take", fields.get("hot_take"), 1, 1000, &mut problems);
+  check_range("max_pages", fields.get("max_pages"), 1, 100, &mut problems);
+  check_range("page_delay_ms", fields.get("page_delay_ms"), 0, 60000, &mut problems);
+  check_range("request_timeout_secs", fields.get("request_timeout_secs"), 1, 600, &mut problems);
+  for name in &["max_replies_per_run", "max_replies_per_hour", "max_replies_per_thread", "max_replies_per_author"] {
+    check_range(name, fields.get(*name), 1, 10000, &mut problems);
+  }
+  check_range("metrics_port", fields.get("metrics_port"), 1, 65535, &mut problems);
+  if let Some(textfile) = fields.get("metrics_textfile").and_then(Value::as_str) {
+    if !textfile.ends_with(".prom") {
+      problems.push("metrics_textfile: must end in .prom, or the textfile collector won't read it".to_string());
+    }
+  }
+  check_range("health_max_age_secs", fields.get("health_max_age_secs"), 60, 604800, &mut problems);
+  check_range("breaker_threshold", fields.get("breaker_threshold"), 1, 1000, &mut problems);
+  check_range("interval_secs", fields.get("interval_secs"), 10, 86400, &mut problems);
+  check_range("jitter_secs", fields.get("jitter_secs"), 0, 3600, &mut problems);
+  check_range("outbox_max_attempts", fields.get("outbox_max_attempts"), 1, 100, &mut problems);
+  ["api_url", "auth_url"].iter().for_each(|name| check_url(fields, name, &mut problems));
+  check_user_agent(fields, &mut problems);
+  check_thread_dedup(fields, &mut problems);
+  check_quiet_hours(fields, &mut problems);
+  check_logging(fields, &mut problems);
+  problems
+}
+
+fn check_fields(fields: &Map<String, Value>, problems: &mut Vec<String>) {
+  for name in fields.keys() {
+    if !FIELDS.iter().any(|(field, _)| field == name) {
+      problems.push(format!("{}: unknown field", name));
+    }
+  }
+  for (name, kind) in FIELDS {
+    match (fields.get(*name), kind) {
+      (None, _) if REQUIRED.contains(name) && SECRETS.contains(name) => problems.push(format!(
+        "{}: missing field; set it in the config, a secrets file, $CREDENTIALS_DIRECTORY or RRBOT_{}",
+        name, name.to_uppercase()
+      )),
+      (None, _) if REQUIRED.contains(name) => problems.push(format!("{}: missing field", name)),
+      (Some(value), Kind::Number) if !value.is_u64() => problems.push(format!("{}: expected a whole number, found {}", name, value)),
+      // a secret's value is left out of the message, as it is from --print-config
+      (Some(value), Kind::Text) if !value.is_string() && !value.is_null() && SECRETS.contains(name) => {
+        problems.push(format!("{}: expected a string, found a {}", name, type_name(value)))
+      },
+      (Some(value), Kind::Text) if !value.is_string() && !value.is_null() => problems.push(format!("{}: expected a string, found {}", name, value)),
+      (Some(value), Kind::Subs) if !value.is_array() => problems.push(format!("{}: expected a list of subreddits, found {}", name, value)),
+      (Some(value), Kind::Sorts) => check_sorts(name, Some(value), problems),
+      (Some(value), Kind::List) if !value.as_array().is_some_and(|items| items.iter().all(Value::is_string)) => {
+        problems.push(format!("{}: expected a list of strings, found {}", name, value))
+      },
+      _ => (),
+    }
+  }
+}
+
+fn type_name(value: &Value) -> &'static str {
+  match value {
+    Value::Null => "null",
+    Value::Bool(_) => "boolean",
+    Value::Number(_) => "number",
+    Value::String(_) => "string",
+    Value::Array(_) => "list",
+    Value::Object(_) => "map",
+  }
+}
+
+fn check_sub_name(path: &str, sub: &str, problems: &mut Vec<String>) {
+  if sub.trim().is_empty() {
+    problems.push(format!("{}: must not be empty", path));
+  } else if sub.starts_with("r/") || sub.starts_with("/r/") {
+    problems.push(format!("{}: leave out the r/ prefix, e.g. \"{}\"", path, sub.trim_start_matches('/').trim_start_matches("r/")));
+  } else if !sub.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+    problems.push(format!("{}: \"{}\" is not a subreddit name (letters, digits and _ only)", path, sub));
+  }
+}
+
+const SUB_FIELDS: &[&str] = &["name", "hot_take", "sorts", "rules", "replies", "posts_db_filename", "comments_db_filename", "outbox_filename"];
+
+fn check_subs(fields: &Map<String, Value>, problems: &mut Vec<String>) {
+  let subs = match fields.get("subs") {
+    Some(Value::Array(subs)) => subs,
+    Some(_) => return,
+    None => {
+      if !fields.contains_key("sub") {
+        problems.push("sub: missing field; set sub, or list subreddits under subs".to_string());
+      }
+      return;
+    },
+  };
+  if subs.is_empty() && !fields.contains_key("sub") {
+    problems.push("subs: must list at least one subreddit".to_string());
+  }
+  if !subs.is_empty() && fields.contains_key("sub") {
+    problems.push("sub: set as well as subs, which would be scanned instead; list it under subs or remove it".to_string());
+  }
+
+  let mut names = Vec::new();
+  for (index, sub) in subs.iter().enumerate() {
+    let path = format!("subs[{}]", index);
+    let sub = match sub.as_object() {
+      Some(sub) => sub,
+      None => {
+        problems.push(format!("{}: expected an object with a name, found {}", path, sub));
+        continue;
+      },
+    };
+    for name in sub.keys() {
+      if !SUB_FIELDS.contains(&name.as_str()) {
+        problems.push(format!("{}.{}: unknown field", path, name));
+      }
+    }
+
+    match sub.get("name").and_then(Value::as_str) {
+      Some(name) => {
+        check_sub_name(&format!("{}.name", path), name, problems);
+        if names.contains(&name.to_lowercase()) {
+          problems.push(format!("{}.name: {} is listed more than once", path, name));
+        }
+        names.push(name.to_lowercase());
+      },
+      None => problems.push(format!("{}.name: missing field", path)),
+    }
+
+    match sub.get("hot_take") {
+      Some(value) if !value.is_u64() => problems.push(format!("{}.hot_take: expected a whole number, found {}", path, value)),
+      value => check_range(&format!("{}.hot_take", path), value, 1, 1000, problems),
+    }
+    for name in &["posts_db_filename", "comments_db_filename", "outbox_filename"] {
+      check_path(&format!("{}.{}", path, name), sub.get(*name), problems);
+    }
+    // a db left from when there was only one sub would otherwise be passed over for an empty
+    // one, and everything in the scan depth answered again
+    if let (Some(name), true) = (sub.get("name").and_then(Value::as_str), subs.len() > 1) {
+      for field in &["posts_db_filename", "comments_db_filename"] {
+        let shared = match fields.get(*field).and_then(Value::as_str) {
+          Some(shared) if !sub.contains_key(*field) => shared,
+          _ => continue,
+        };
+        let own = namespaced(shared, name);
+        if Path::new(shared).exists() && !Path::new(&own).exists() {
+          problems.push(format!(
+            "{}.{}: {} exists but {} doesn't; rename it if it's this sub's, or create {} empty to start afresh",
+            path, field, shared, own, own
+          ));
+        }
+      }
+    }
+    check_sorts(&format!("{}.sorts", path), sub.get("sorts"), problems);
+    check_rules(&path, sub, problems);
+  }
+}
+
+fn check_sorts(path: &str, value: Option<&Value>, problems: &mut Vec<String>) {
+  match value {
+    Some(Value::Array(sorts)) if sorts.is_empty() => problems.push(format!("{}: must list at least one sort", path)),
+    Some(Value::Array(sorts)) => for (index, sort) in sorts.iter().enumerate() {
+      if !sort.as_str().is_some_and(|sort| SORTS.contains(&sort)) {
+        problems.push(format!("{}[{}]: unknown sort {}, expected one of {}", path, index, sort, SORTS.join(", ")));
+      }
+    },
+    Some(sorts) => problems.push(format!("{}: expected a list of sorts, found {}", path, sorts)),
+    None => (),
+  }
+}
+
+fn check_rules(path: &str, sub: &Map<String, Value>, problems: &mut Vec<String>) {
+  let known = RULES.iter().map(|rule| rule.name.as_str()).collect::<Vec<_>>();
+  match sub.get("rules") {
+    Some(Value::Array(rules)) => for (index, rule) in rules.iter().enumerate() {
+      match rule.as_str() {
+        Some(rule) if known.contains(&rule) => (),
+        _ => problems.push(format!("{}.rules[{}]: unknown rule {}, expected one of {}", path, index, rule, known.join(", "))),
+      }
+    },
+    Some(rules) => problems.push(format!("{}.rules: expected a list of rule names, found {}", path, rules)),
+    None => (),
+  }
+  match sub.get("replies") {
+    Some(Value::Object(replies)) => for (rule, reply) in replies {
+      if !known.contains(&rule.as_str()) {
+        problems.push(format!("{}.replies.{}: unknown rule, expected one of {}", path, rule, known.join(", ")));
+      }
+      if reply.as_str().is_none_or(|reply| reply.trim().is_empty()) {
+        problems.push(format!("{}.replies.{}: expected the text of a reply, found {}", path, rule, reply));
+      }
+    },
+    Some(replies) => problems.push(format!("{}.replies: expected rule names mapped to replies, found {}", path, replies)),
+    None => (),
+  }
+}
+
+fn check_path(path: &str, value: Option<&Value>, problems: &mut Vec<String>) {
+  let filename = match value.and_then(Value::as_str) {
+    Some(filename) => filename,
+    None => return,
+  };
+  if filename.trim().is_empty() {
+    problems.push(format!("{}: must not be empty", path));
+    return;
+  }
+  let filename = Path::new(filename);
+  if filename.is_dir() {
+    problems.push(format!("{}: {} is a directory", path, filename.display()));
+  }
+  if let Some(dir) = filename.parent() {
+    if !dir.as_os_str().is_empty() && !dir.is_dir() {
+      problems.push(format!("{}: directory {} does not exist", path, dir.display()));
+    }
+  }
+}
+
+fn check_range(path: &str, value: Option<&Value>, min: u64, max: u64, problems: &mut Vec<String>) {
+  if let Some(value) = value.and_then(Value::as_u64) {
+    if value < min || value > max {
+      problems.push(format!("{}: {} is out of range, expected {} to {}", path, value, min, max));
+    }
+  }
+}
+
+fn check_url(fields: &Map<String, Value>, name: &str, problems: &mut Vec<String>) {
+  if let Some(url) = fields.get(name).and_then(Value::as_str) {
+    if !url.starts_with("https://") && !url.starts_with("http://") {
+      problems.push(format!("{}: \"{}\" is not an http(s) URL", name, url));
+    }
+  }
+}
+
+// reddit identifies clients by user agent, so each part has to be present and keep its shape
+fn check_user_agent(fields: &Map<String, Value>, problems: &mut Vec<String>) {
+  let part = |name: &str| fields.get(name).and_then(Value::as_str);
+  for name in &["platform", "app_id", "app_version"] {
+    match part(name) {
+      Some(value) if value.trim().is_empty() => problems.push(format!("{}: must not be empty", name)),
+      Some(value) if value.contains(':') || value.contains(char::is_whitespace) => {
+        problems.push(format!("{}: \"{}\" must not contain colons or spaces", name, value))
+      },
+      _ => (),
+    }
+  }
+  if let Some(app_id) = part("app_id") {
+    if !app_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_' || c == '-') {
+      problems.push(format!("app_id: \"{}\" is not an app id (letters, digits, ., _ and - only), e.g. com.example.rrbot", app_id));
+    }
+  }
+  // the contact is the username unless set, so check whichever ends up in the user agent
+  let contact = match part("contact") {
+    Some(contact) => ("contact", contact),
+    None => match part("username") {
+      Some(username) => ("username", username),
+      None => return,
+    },
+  };
+  check_username(contact.0, contact.1, problems);
+}
+
+fn check_username(path: &str, username: &str, problems: &mut Vec<String>) {
+  if username.starts_with("u/") || username.starts_with("/u/") {
+    problems.push(format!("{}: leave out the u/ prefix, e.g. \"{}\"", path, username.trim_start_matches('/').trim_start_matches("u/")));
+  } else if username.len() < 3 || username.len() > 20 || !username.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+    problems.push(format!("{}: \"{}\" is not a reddit username (3 to 20 letters, digits, _ or -)", path, username));
+  }
+}
+
+fn check_thread_dedup(fields: &Map<String, Value>, problems: &mut Vec<String>) {
+  if let Some(dedup) = fields.get("thread_dedup").and_then(Value::as_str) {
+    if !THREAD_DEDUP.contains(&dedup) {
+      problems.push(format!("thread_dedup: unknown mode \"{}\", expected one of {}", dedup, THREAD_DEDUP.join(", ")));
+    }
+  }
+  if let Some(reply) = fields.get("see_above_reply").and_then(Value::as_str) {
+    if !reply.contains("{link}") {
+      problems.push("see_above_reply: must contain {link}, where the link to the first reply goes".to_string());
+    }
+  }
+}
+
+fn check_logging(fields: &Map<String, Value>, problems: &mut Vec<String>) {
+  if let Some(level) = fields.get("log_level").and_then(Value::as_str) {
+    if let Err(e) = Filter::parse(level) {
+      problems.push(format!("log_level: {}", e));
+    }
+  }
+  if let Some(format) = fields.get("log_format").and_then(Value::as_str) {
+    if !LOG_FORMATS.contains(&format) {
+      problems.push(format!("log_format: unknown format \"{}\", expected one of {}", format, LOG_FORMATS.join(", ")));
+    }
+  }
+}
+
+fn check_quiet_hours(fields: &Map<String, Value>, problems: &mut Vec<String>) {
+  if let Some(windows) = fields.get("quiet_hours").and_then(Value::as_array) {
+    for (index, window) in windows.iter().enumerate() {
+      if let Some(window) = window.as_str() {
+        if Period::parse(window).is_none() {
+          problems.push(format!(
+            "quiet_hours[{}]: \"{}\" is not a UTC window, e.g. \"22:00-06:00\" or \"2026-10-20T18:00/2026-10-20T22:00\"",
+            index, window
+          ));
+        }
+      }
+    }
+  }
+}